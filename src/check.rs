@@ -0,0 +1,784 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context as ErrorContext, Result};
+use p2panda_rs::schema::{FieldName, SchemaDescription, SchemaId, SchemaName};
+use regex::Regex;
+use serde::de::{MapAccess, Visitor};
+use serde::{Deserialize, Deserializer};
+use toml::Spanned;
+
+use crate::files::{
+    expand_include, read_schema_file, SchemaField, SchemaFormat, SchemaItem, INCLUDE_KEY,
+    MIXINS_KEY,
+};
+
+/// Maximum length of a field name.
+const MAX_FIELD_NAME_LENGTH: usize = 64;
+
+/// Names of the field types which are no relations.
+pub const FIELD_TYPES: [&str; 4] = ["bool", "float", "int", "str"];
+
+/// Names of the relation field types.
+pub const RELATION_TYPES: [&str; 4] = [
+    "relation",
+    "relation_list",
+    "pinned_relation",
+    "pinned_relation_list",
+];
+
+/// Position of a problem inside of a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub line: usize,
+    pub column: usize,
+    snippet: String,
+    length: usize,
+}
+
+impl Location {
+    /// Location of the byte range in the content.
+    fn from_span(content: &str, span: Range<usize>) -> Self {
+        let start = span.start.min(content.len());
+        let line_start = content[..start].rfind('\n').map_or(0, |index| index + 1);
+        let snippet = content[line_start..].lines().next().unwrap_or_default();
+        let end = span.end.min(line_start + snippet.len()).max(start);
+
+        Self {
+            line: content[..start].matches('\n').count() + 1,
+            column: content[line_start..start].chars().count() + 1,
+            snippet: snippet.to_string(),
+            length: content[start..end].chars().count().max(1),
+        }
+    }
+
+    /// Location of a line and column, both starting at 1.
+    fn from_line_column(content: &str, line: usize, column: usize) -> Self {
+        let line = line.max(1);
+
+        Self {
+            line,
+            column: column.max(1),
+            snippet: content
+                .lines()
+                .nth(line - 1)
+                .unwrap_or_default()
+                .to_string(),
+            length: 1,
+        }
+    }
+}
+
+/// Problem found in a schema file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub path: PathBuf,
+    pub location: Option<Location>,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "error: {}", self.message)?;
+
+        let location = match &self.location {
+            Some(location) => location,
+            None => return write!(f, " --> {}", self.path.display()),
+        };
+
+        let gutter = " ".repeat(location.line.to_string().len());
+
+        writeln!(
+            f,
+            "{gutter}--> {}:{}:{}",
+            self.path.display(),
+            location.line,
+            location.column
+        )?;
+        writeln!(f, "{gutter} |")?;
+        writeln!(f, "{} | {}", location.line, location.snippet)?;
+        write!(
+            f,
+            "{gutter} | {}{}",
+            " ".repeat(location.column - 1),
+            "^".repeat(location.length)
+        )
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RawRelationSchema {
+    name: Option<Spanned<String>>,
+    id: Option<Spanned<String>>,
+    git: Option<String>,
+    path: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawField {
+    #[serde(rename = "type")]
+    field_type: Spanned<String>,
+    schema: Option<RawRelationSchema>,
+    fields: Option<BTreeMap<Spanned<String>, RawField>>,
+    values: Option<Spanned<Vec<String>>>,
+    min: Option<Spanned<f64>>,
+    max: Option<Spanned<f64>>,
+    pattern: Option<Spanned<String>>,
+    optional: Option<Spanned<bool>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawSchemaItem {
+    description: Option<Spanned<String>>,
+    extends: Option<Spanned<toml::Value>>,
+    fields: Option<BTreeMap<Spanned<String>, RawField>>,
+}
+
+/// Schema file which keeps the positions of all names and values.
+///
+/// Tables are not wrapped in `Spanned` as their positions are unknown when they are defined
+/// implicitly by dotted keys, diagnostics about them point at their name instead.
+#[derive(Debug, Default)]
+struct RawSchemaFile {
+    include: Vec<Spanned<String>>,
+    mixins: BTreeMap<Spanned<String>, BTreeMap<Spanned<String>, RawField>>,
+    schemas: Vec<(Spanned<String>, RawSchemaItem)>,
+}
+
+impl<'de> Deserialize<'de> for RawSchemaFile {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct RawSchemaFileVisitor;
+
+        impl<'de> Visitor<'de> for RawSchemaFileVisitor {
+            type Value = RawSchemaFile;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a map of schemas")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let mut schema_file = RawSchemaFile::default();
+
+                while let Some(key) = map.next_key::<Spanned<String>>()? {
+                    if key.get_ref() == INCLUDE_KEY {
+                        schema_file.include = map.next_value()?;
+                    } else if key.get_ref() == MIXINS_KEY {
+                        schema_file.mixins = map.next_value()?;
+                    } else {
+                        schema_file.schemas.push((key, map.next_value()?));
+                    }
+                }
+
+                Ok(schema_file)
+            }
+        }
+
+        deserializer.deserialize_map(RawSchemaFileVisitor)
+    }
+}
+
+/// Schema file which was read for checking.
+struct CheckedFile {
+    path: PathBuf,
+    content: String,
+    schema_file: RawSchemaFile,
+
+    /// True if the positions point into the original file, which is only the case for TOML.
+    has_locations: bool,
+}
+
+impl CheckedFile {
+    fn diagnostic(&self, span: Range<usize>, message: String) -> Diagnostic {
+        Diagnostic {
+            path: self.path.clone(),
+            location: self
+                .has_locations
+                .then(|| Location::from_span(&self.content, span)),
+            message,
+        }
+    }
+}
+
+/// Returns true if the name can be used for a field.
+pub fn is_valid_field_name(name: &str) -> bool {
+    let mut chars = name.chars();
+
+    name.len() <= MAX_FIELD_NAME_LENGTH
+        && chars
+            .next()
+            .map_or(false, |char| char.is_ascii_alphabetic())
+        && chars.all(|char| char.is_ascii_alphanumeric() || char == '_')
+}
+
+/// Compiled patterns of the fields of a schema, by field name.
+pub type FieldPatterns = BTreeMap<FieldName, Regex>;
+
+/// Compiles the patterns of all fields of a schema, failing at the first invalid one.
+pub fn compile_patterns(
+    schema_name: &SchemaName,
+    schema_item: &SchemaItem,
+) -> Result<FieldPatterns> {
+    let mut patterns = FieldPatterns::new();
+
+    for (field_name, field) in schema_item.fields.iter() {
+        if let SchemaField::Field { constraints, .. } = field {
+            if let Some(pattern) = &constraints.pattern {
+                let regex = Regex::new(pattern).with_context(|| {
+                    format!("Invalid pattern of field '{field_name}' of schema {schema_name}")
+                })?;
+                patterns.insert(field_name.clone(), regex);
+            }
+        }
+    }
+
+    Ok(patterns)
+}
+
+/// Parses a schema file, keeping the positions of its values if possible.
+fn parse_file(path: &Path, format: SchemaFormat) -> Result<CheckedFile, Diagnostic> {
+    let diagnostic = |location, message| Diagnostic {
+        path: path.to_path_buf(),
+        location,
+        message,
+    };
+
+    let content = fs::read_to_string(path)
+        .map_err(|err| diagnostic(None, format!("Could not read file: {err}")))?;
+
+    // Other formats are checked as TOML, their errors are reported without a position
+    let toml_content = match format {
+        SchemaFormat::Toml => content.clone(),
+        SchemaFormat::Yaml => serde_yaml::from_str::<toml::Table>(&content)
+            .map_err(|err| {
+                let location = err.location().map(|location| {
+                    Location::from_line_column(&content, location.line(), location.column())
+                });
+                diagnostic(location, format!("Invalid YAML: {err}"))
+            })?
+            .to_string(),
+        SchemaFormat::Json => serde_json::from_str::<toml::Table>(&content)
+            .map_err(|err| {
+                let location = Location::from_line_column(&content, err.line(), err.column());
+                diagnostic(Some(location), format!("Invalid JSON: {err}"))
+            })?
+            .to_string(),
+    };
+
+    let schema_file: RawSchemaFile = toml::from_str(&toml_content).map_err(|err| {
+        let location = match (format, err.span()) {
+            (SchemaFormat::Toml, Some(span)) => Some(Location::from_span(&content, span)),
+            _ => None,
+        };
+        diagnostic(location, err.message().to_string())
+    })?;
+
+    Ok(CheckedFile {
+        path: path.to_path_buf(),
+        content,
+        schema_file,
+        has_locations: format == SchemaFormat::Toml,
+    })
+}
+
+/// Reads the file and all files it includes.
+fn read_files(
+    path: &Path,
+    format: SchemaFormat,
+    files: &mut Vec<CheckedFile>,
+    diagnostics: &mut Vec<Diagnostic>,
+    stack: &mut Vec<PathBuf>,
+) {
+    let file = match parse_file(path, format) {
+        Ok(file) => file,
+        Err(diagnostic) => return diagnostics.push(diagnostic),
+    };
+
+    let canonical_path = fs::canonicalize(path).unwrap_or(path.to_path_buf());
+    let base = path.parent().unwrap_or(Path::new("")).to_path_buf();
+    let mut included_paths = Vec::new();
+
+    for include in &file.schema_file.include {
+        match expand_include(&base, include.get_ref()) {
+            Ok(paths) => {
+                included_paths.extend(paths.into_iter().map(|path| (include.span(), path)))
+            }
+            Err(err) => diagnostics.push(file.diagnostic(include.span(), format!("{err:#}"))),
+        }
+    }
+
+    // Keep the order of `read_schema_file`, where a file comes before the files it includes
+    let index = files.len();
+    files.push(file);
+
+    for (span, included_path) in included_paths {
+        let is_cycle = fs::canonicalize(&included_path).map_or(false, |path| {
+            path == canonical_path || stack.contains(&path)
+        });
+
+        if is_cycle {
+            diagnostics.push(files[index].diagnostic(
+                span,
+                format!("{} is included in a cycle", included_path.display()),
+            ));
+            continue;
+        }
+
+        stack.push(canonical_path.clone());
+        read_files(
+            &included_path,
+            SchemaFormat::from_path(&included_path),
+            files,
+            diagnostics,
+            stack,
+        );
+        stack.pop();
+    }
+}
+
+fn check_relation(
+    file: &CheckedFile,
+    field_name: &Spanned<String>,
+    field: &RawField,
+    defined: &BTreeMap<String, PathBuf>,
+    external: &[SchemaName],
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let schema = match &field.schema {
+        Some(schema) => schema,
+        None => {
+            diagnostics.push(file.diagnostic(
+                field_name.span(),
+                "Relation is missing the related 'schema'".to_string(),
+            ));
+            return;
+        }
+    };
+
+    match (&schema.name, &schema.id) {
+        (Some(name), None) => {
+            if SchemaName::new(name.get_ref()).is_err() {
+                diagnostics.push(file.diagnostic(
+                    name.span(),
+                    format!("Invalid schema name '{}'", name.get_ref()),
+                ));
+                return;
+            }
+
+            let is_external = schema.path.is_some() || schema.git.is_some();
+            let is_known = defined.contains_key(name.get_ref())
+                || external
+                    .iter()
+                    .any(|external| external.to_string() == *name.get_ref());
+
+            if !is_external && !is_known {
+                diagnostics.push(file.diagnostic(
+                    name.span(),
+                    format!("Relation to unknown schema '{}'", name.get_ref()),
+                ));
+            }
+        }
+        (None, Some(id)) => {
+            if id.get_ref().parse::<SchemaId>().is_err() {
+                diagnostics.push(
+                    file.diagnostic(id.span(), format!("Invalid schema id '{}'", id.get_ref())),
+                );
+            }
+        }
+        _ => diagnostics.push(file.diagnostic(
+            field_name.span(),
+            "Related schema needs either a 'name' or an 'id'".to_string(),
+        )),
+    }
+}
+
+/// Checks that the local constraints of a field fit its type.
+fn check_constraints(file: &CheckedFile, field: &RawField, diagnostics: &mut Vec<Diagnostic>) {
+    let field_type = field.field_type.get_ref().as_str();
+    let is_string = field_type == "str";
+    let is_number = field_type == "int" || field_type == "float";
+
+    let mut push = |span: Range<usize>, message: String| {
+        diagnostics.push(file.diagnostic(span, message));
+    };
+
+    if let Some(values) = &field.values {
+        if !is_string {
+            push(
+                values.span(),
+                format!("Fields of type '{field_type}' can't have 'values'"),
+            );
+        } else if values.get_ref().is_empty() {
+            push(
+                values.span(),
+                "'values' needs at least one value".to_string(),
+            );
+        }
+    }
+
+    if let Some(pattern) = &field.pattern {
+        if !is_string {
+            push(
+                pattern.span(),
+                format!("Fields of type '{field_type}' can't have a 'pattern'"),
+            );
+        } else if Regex::new(pattern.get_ref()).is_err() {
+            // The error of the regex spans several lines, the location points at the problem
+            push(
+                pattern.span(),
+                format!("Invalid regular expression '{}'", pattern.get_ref()),
+            );
+        }
+    }
+
+    for bound in [&field.min, &field.max].into_iter().flatten() {
+        if !is_number {
+            push(
+                bound.span(),
+                format!("Fields of type '{field_type}' can't have 'min' or 'max'"),
+            );
+        }
+    }
+
+    if let (Some(min), Some(max)) = (&field.min, &field.max) {
+        if min.get_ref() > max.get_ref() {
+            push(max.span(), "'max' is smaller than 'min'".to_string());
+        }
+    }
+
+    if let Some(optional) = &field.optional {
+        if !FIELD_TYPES.contains(&field_type) {
+            push(
+                optional.span(),
+                "Only fields of type bool, float, int or str can be 'optional'".to_string(),
+            );
+        }
+    }
+}
+
+fn check_fields(
+    file: &CheckedFile,
+    fields: &BTreeMap<Spanned<String>, RawField>,
+    defined: &BTreeMap<String, PathBuf>,
+    external: &[SchemaName],
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    for (field_name, field) in fields {
+        if !is_valid_field_name(field_name.get_ref()) {
+            diagnostics.push(file.diagnostic(
+                field_name.span(),
+                format!(
+                    "Invalid field name '{}', it has to start with a letter and can only \
+                    contain letters, numbers and underscores",
+                    field_name.get_ref()
+                ),
+            ));
+        }
+
+        let field_type = &field.field_type;
+
+        check_constraints(file, field, diagnostics);
+
+        if let Some(nested_fields) = &field.fields {
+            if !["relation", "relation_list"].contains(&field_type.get_ref().as_str()) {
+                diagnostics.push(file.diagnostic(
+                    field_type.span(),
+                    "Nested fields have to be of type 'relation' or 'relation_list'".to_string(),
+                ));
+            }
+
+            if field.schema.is_some() {
+                diagnostics.push(file.diagnostic(
+                    field_name.span(),
+                    "Field with nested fields can't relate to another schema".to_string(),
+                ));
+            }
+
+            if nested_fields.is_empty() {
+                diagnostics.push(file.diagnostic(
+                    field_name.span(),
+                    format!(
+                        "Nested field '{}' doesn't have any fields",
+                        field_name.get_ref()
+                    ),
+                ));
+            }
+
+            check_fields(file, nested_fields, defined, external, diagnostics);
+        } else if RELATION_TYPES.contains(&field_type.get_ref().as_str()) {
+            check_relation(file, field_name, field, defined, external, diagnostics);
+        } else if FIELD_TYPES.contains(&field_type.get_ref().as_str()) {
+            if field.schema.is_some() {
+                diagnostics.push(file.diagnostic(
+                    field_name.span(),
+                    format!(
+                        "Field of type '{}' can't relate to a schema",
+                        field_type.get_ref()
+                    ),
+                ));
+            }
+        } else {
+            diagnostics.push(file.diagnostic(
+                field_type.span(),
+                format!(
+                    "Unknown field type '{}', expected one of: {}, {}",
+                    field_type.get_ref(),
+                    FIELD_TYPES.join(", "),
+                    RELATION_TYPES.join(", ")
+                ),
+            ));
+        }
+    }
+}
+
+/// Returns the names of the mixins and schemas a schema extends.
+fn extended_names(
+    file: &CheckedFile,
+    extends: &Spanned<toml::Value>,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Vec<String> {
+    let names = match extends.get_ref() {
+        toml::Value::String(name) => Some(vec![name.clone()]),
+        toml::Value::Array(values) => values
+            .iter()
+            .map(|value| value.as_str().map(str::to_string))
+            .collect(),
+        _ => None,
+    };
+
+    names.unwrap_or_else(|| {
+        diagnostics.push(file.diagnostic(
+            extends.span(),
+            "'extends' has to be a name or a list of names".to_string(),
+        ));
+        Vec::new()
+    })
+}
+
+fn check_file(
+    file: &CheckedFile,
+    defined: &BTreeMap<String, PathBuf>,
+    mixins: &BTreeMap<String, PathBuf>,
+    external: &[SchemaName],
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    for (name, fields) in &file.schema_file.mixins {
+        if defined.contains_key(name.get_ref()) {
+            diagnostics.push(file.diagnostic(
+                name.span(),
+                format!("'{}' is both a mixin and a schema", name.get_ref()),
+            ));
+        }
+
+        check_fields(file, fields, defined, external, diagnostics);
+    }
+
+    for (name, item) in &file.schema_file.schemas {
+        if SchemaName::new(name.get_ref()).is_err() {
+            diagnostics.push(file.diagnostic(
+                name.span(),
+                format!("Invalid schema name '{}'", name.get_ref()),
+            ));
+        }
+
+        match &item.description {
+            Some(description) => {
+                if let Err(err) = SchemaDescription::new(description.get_ref()) {
+                    diagnostics.push(
+                        file.diagnostic(description.span(), format!("Invalid description: {err}")),
+                    );
+                }
+            }
+            None => diagnostics.push(file.diagnostic(
+                name.span(),
+                format!("Schema '{}' is missing a description", name.get_ref()),
+            )),
+        }
+
+        let mut extends = Vec::new();
+        if let Some(extends_value) = &item.extends {
+            extends = extended_names(file, extends_value, diagnostics);
+
+            for extended in &extends {
+                if !defined.contains_key(extended) && !mixins.contains_key(extended) {
+                    diagnostics.push(file.diagnostic(
+                        extends_value.span(),
+                        format!("Unknown mixin or schema '{extended}'"),
+                    ));
+                }
+            }
+        }
+
+        match &item.fields {
+            Some(fields) if !fields.is_empty() => {
+                check_fields(file, fields, defined, external, diagnostics)
+            }
+            // Extending schemas get their fields from the extended ones
+            _ if !extends.is_empty() => (),
+            _ => diagnostics.push(file.diagnostic(
+                name.span(),
+                format!("Schema '{}' doesn't have any fields", name.get_ref()),
+            )),
+        }
+    }
+}
+
+/// Collects the given names of all files, reporting names which are defined more than once.
+fn collect_names<'a>(
+    files: &'a [CheckedFile],
+    kind: &str,
+    names: impl Fn(&'a CheckedFile) -> Vec<&'a Spanned<String>>,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> BTreeMap<String, PathBuf> {
+    let mut defined: BTreeMap<String, PathBuf> = BTreeMap::new();
+
+    for file in files {
+        for name in names(file) {
+            match defined.get(name.get_ref()) {
+                Some(other_path) => diagnostics.push(file.diagnostic(
+                    name.span(),
+                    format!(
+                        "{kind} '{}' is already defined in {}",
+                        name.get_ref(),
+                        other_path.display()
+                    ),
+                )),
+                None => {
+                    defined.insert(name.get_ref().clone(), file.path.clone());
+                }
+            }
+        }
+    }
+
+    defined
+}
+
+/// Checks a schema file and all files it includes, returning every problem found.
+///
+/// Relations may point to the given external schemas, defined outside of the schema file.
+pub fn check_schema_file(
+    path: &Path,
+    format: SchemaFormat,
+    external: &[SchemaName],
+) -> Vec<Diagnostic> {
+    let mut files = Vec::new();
+    let mut diagnostics = Vec::new();
+    read_files(path, format, &mut files, &mut diagnostics, &mut Vec::new());
+
+    let defined = collect_names(
+        &files,
+        "Schema",
+        |file| {
+            file.schema_file
+                .schemas
+                .iter()
+                .map(|(name, _)| name)
+                .collect()
+        },
+        &mut diagnostics,
+    );
+    let mixins = collect_names(
+        &files,
+        "Mixin",
+        |file| file.schema_file.mixins.keys().collect(),
+        &mut diagnostics,
+    );
+
+    for file in &files {
+        check_file(file, &defined, &mixins, external, &mut diagnostics);
+    }
+
+    // Problems of the expanded schemas, like conflicting inherited fields, have no position
+    if diagnostics.is_empty() {
+        if let Err(err) = read_schema_file(path, format) {
+            diagnostics.push(Diagnostic {
+                path: path.to_path_buf(),
+                location: None,
+                message: format!("{err:#}"),
+            });
+        }
+    }
+
+    diagnostics
+}
+
+/// Checks the schema file, printing all problems and failing if there are any.
+pub fn ensure_valid_schema_file(
+    path: &Path,
+    format: SchemaFormat,
+    external: &[SchemaName],
+) -> Result<()> {
+    let diagnostics = check_schema_file(path, format, external);
+
+    for diagnostic in &diagnostics {
+        eprintln!("{diagnostic}\n");
+    }
+
+    match diagnostics.len() {
+        0 => Ok(()),
+        1 => bail!("Found 1 problem in {}", path.display()),
+        count => bail!("Found {count} problems in {}", path.display()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{check_schema_file, Location};
+    use crate::files::SchemaFormat;
+
+    #[test]
+    fn locations() {
+        let content = "[post]\ndescription = \"\"\n\n[post.fields]\nTitle-1 = { type = \"str\" }\n";
+        let start = content.find("Title-1").unwrap();
+
+        let location = Location::from_span(content, start..start + 7);
+        assert_eq!(location.line, 5);
+        assert_eq!(location.column, 1);
+        assert_eq!(location.snippet, "Title-1 = { type = \"str\" }");
+        assert_eq!(location.length, 7);
+    }
+
+    #[test]
+    fn reports_problems() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("schema.toml");
+
+        fs::write(
+            &path,
+            r#"
+[post]
+description = ""
+fields.title.type = "str"
+
+[post.fields.author]
+type = "relation"
+schema = { name = "author" }
+
+[comment]
+description = ""
+
+[comment.fields]
+1text = { type = "string" }
+"#,
+        )
+        .unwrap();
+
+        let diagnostics = check_schema_file(&path, SchemaFormat::Toml, &[]);
+        let messages: Vec<&str> = diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.message.as_str())
+            .collect();
+
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[0], "Relation to unknown schema 'author'");
+        assert!(messages[1].starts_with("Invalid field name '1text'"));
+        assert!(messages[2].starts_with("Unknown field type 'string'"));
+        assert_eq!(diagnostics[0].location.as_ref().unwrap().line, 8);
+    }
+}
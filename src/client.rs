@@ -0,0 +1,378 @@
+use std::env;
+use std::fmt;
+use std::fs;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context as ErrorContext, Result};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION};
+use reqwest::{Certificate, Identity};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::files::NodeConfig;
+
+/// Endpoint used when neither the command line nor the config file specify one.
+pub const DEFAULT_ENDPOINT: &str = "http://localhost:2020/graphql";
+
+#[derive(Deserialize, Debug)]
+struct GraphQLError {
+    message: String,
+}
+
+/// Errors a node answered a GraphQL request with.
+///
+/// Unlike transport or HTTP errors, these show that the node received and processed the request.
+#[derive(Debug)]
+pub struct GraphQLErrors(pub Vec<String>);
+
+impl fmt::Display for GraphQLErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0.join(", "))
+    }
+}
+
+impl std::error::Error for GraphQLErrors {}
+
+#[derive(Deserialize, Debug)]
+struct GraphQLResponse<T> {
+    data: Option<T>,
+    errors: Option<Vec<GraphQLError>>,
+}
+
+/// Reads the bearer token from the configured environment variable or file.
+///
+/// Environment variables are looked up with `env_var`, so tests don't depend on the process
+/// environment.
+fn read_token(
+    config: &NodeConfig,
+    env_var: impl Fn(&str) -> Option<String>,
+) -> Result<Option<String>> {
+    if config.token_env.is_some() && config.token_file.is_some() {
+        bail!("Token environment variable and token file can't be given together");
+    }
+
+    if let Some(name) = &config.token_env {
+        let token = env_var(name)
+            .with_context(|| format!("Could not read token from environment variable {name}"))?;
+        return Ok(Some(token.trim().to_owned()));
+    }
+
+    if let Some(path) = &config.token_file {
+        let token = fs::read_to_string(path)
+            .with_context(|| format!("Could not read token file {}", path.display()))?;
+        return Ok(Some(token.trim().to_owned()));
+    }
+
+    Ok(None)
+}
+
+fn build_headers(
+    config: &NodeConfig,
+    env_var: impl Fn(&str) -> Option<String>,
+) -> Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+
+    for (name, value) in &config.headers {
+        let name = HeaderName::from_bytes(name.as_bytes())
+            .with_context(|| format!("Invalid HTTP header name '{name}'"))?;
+        let value = HeaderValue::from_str(value)
+            .with_context(|| format!("Invalid value for HTTP header '{name}'"))?;
+        headers.insert(name, value);
+    }
+
+    if let Some(token) = read_token(config, env_var)? {
+        let mut value = HeaderValue::from_str(&format!("Bearer {token}"))
+            .map_err(|_| anyhow!("Bearer token contains invalid characters"))?;
+        value.set_sensitive(true);
+        headers.insert(AUTHORIZATION, value);
+    }
+
+    Ok(headers)
+}
+
+/// Minimal GraphQL client to talk to a node.
+pub struct Client {
+    endpoint: String,
+    http: reqwest::Client,
+}
+
+impl Client {
+    pub fn new(config: &NodeConfig) -> Result<Self> {
+        Self::with_env(config, |name| env::var(name).ok())
+    }
+
+    fn with_env(config: &NodeConfig, env_var: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        let endpoint = config
+            .endpoint
+            .clone()
+            .unwrap_or(DEFAULT_ENDPOINT.to_string());
+
+        let mut builder =
+            reqwest::Client::builder().default_headers(build_headers(config, env_var)?);
+
+        if let Some(timeout) = config.timeout {
+            builder = builder.timeout(Duration::from_secs(timeout));
+        }
+
+        if let Some(path) = &config.ca_cert {
+            let pem = fs::read(path)
+                .with_context(|| format!("Could not read CA certificate {}", path.display()))?;
+            let certificate = Certificate::from_pem(&pem)
+                .with_context(|| format!("Invalid CA certificate {}", path.display()))?;
+            builder = builder.add_root_certificate(certificate);
+        }
+
+        match (&config.client_cert, &config.client_key) {
+            (Some(cert_path), Some(key_path)) => {
+                let mut pem = fs::read(cert_path).with_context(|| {
+                    format!("Could not read client certificate {}", cert_path.display())
+                })?;
+                pem.extend(fs::read(key_path).with_context(|| {
+                    format!("Could not read client key {}", key_path.display())
+                })?);
+                let identity = Identity::from_pem(&pem)
+                    .with_context(|| "Invalid client certificate or key")?;
+                builder = builder.identity(identity);
+            }
+            (None, None) => (),
+            _ => bail!("Client certificate and client key need to be given together"),
+        }
+
+        Ok(Self {
+            endpoint,
+            http: builder.build()?,
+        })
+    }
+
+    /// Sends a GraphQL query or mutation and returns the deserialized `data` of the response.
+    pub async fn query<T: DeserializeOwned>(&self, query: &str) -> Result<T> {
+        let response = self
+            .http
+            .post(&self.endpoint)
+            .json(&json!({ "query": query }))
+            .send()
+            .await
+            .with_context(|| format!("Could not reach node at {}", self.endpoint))?
+            .error_for_status()?
+            .json::<GraphQLResponse<T>>()
+            .await?;
+
+        if let Some(errors) = response.errors {
+            let messages = errors.into_iter().map(|error| error.message).collect();
+            return Err(GraphQLErrors(messages).into());
+        }
+
+        response
+            .data
+            .ok_or(anyhow!("Missing data in GraphQL response"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::fs;
+    use std::path::Path;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    use rcgen::{BasicConstraints, Certificate, CertificateParams, IsCa};
+    use serde::Deserialize;
+    use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio_rustls::rustls::server::AllowAnyAuthenticatedClient;
+    use tokio_rustls::rustls::{self, RootCertStore, ServerConfig};
+    use tokio_rustls::TlsAcceptor;
+
+    use crate::files::NodeConfig;
+
+    use super::Client;
+
+    #[derive(Deserialize)]
+    struct Pong {
+        pong: bool,
+    }
+
+    /// Answers a single GraphQL request and returns it.
+    async fn respond(mut stream: impl AsyncRead + AsyncWrite + Unpin) -> String {
+        let mut buf = vec![0; 4096];
+        let len = stream.read(&mut buf).await.unwrap();
+        let body = r#"{"data":{"pong":true}}"#;
+        let response = format!(
+            "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        stream.write_all(response.as_bytes()).await.unwrap();
+        stream.shutdown().await.unwrap();
+        String::from_utf8_lossy(&buf[..len]).to_lowercase()
+    }
+
+    fn certificate(is_ca: bool) -> Certificate {
+        let mut params = CertificateParams::new(vec!["localhost".to_string()]);
+        if is_ca {
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        }
+        Certificate::from_params(params).unwrap()
+    }
+
+    /// Local stand-in for a node behind TLS which only accepts clients with a certificate
+    /// signed by its own CA.
+    struct TlsNode {
+        listener: TcpListener,
+        acceptor: TlsAcceptor,
+        ca_pem: String,
+        client_pem: (String, String),
+    }
+
+    impl TlsNode {
+        async fn new() -> Self {
+            let ca = certificate(true);
+            let server = certificate(false);
+            let client = certificate(false);
+
+            let mut roots = RootCertStore::empty();
+            roots
+                .add(&rustls::Certificate(ca.serialize_der().unwrap()))
+                .unwrap();
+
+            let config = ServerConfig::builder()
+                .with_safe_defaults()
+                .with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots).boxed())
+                .with_single_cert(
+                    vec![rustls::Certificate(
+                        server.serialize_der_with_signer(&ca).unwrap(),
+                    )],
+                    rustls::PrivateKey(server.serialize_private_key_der()),
+                )
+                .unwrap();
+
+            Self {
+                listener: TcpListener::bind("127.0.0.1:0").await.unwrap(),
+                acceptor: TlsAcceptor::from(Arc::new(config)),
+                ca_pem: ca.serialize_pem().unwrap(),
+                client_pem: (
+                    client.serialize_pem_with_signer(&ca).unwrap(),
+                    client.serialize_private_key_pem(),
+                ),
+            }
+        }
+
+        fn endpoint(&self) -> String {
+            let port = self.listener.local_addr().unwrap().port();
+            format!("https://localhost:{port}/graphql")
+        }
+
+        /// Writes the CA certificate and, if requested, the client certificate and key into the
+        /// given folder and returns a config using them.
+        fn config(&self, dir: &Path, with_client_cert: bool) -> NodeConfig {
+            fs::write(dir.join("ca.pem"), &self.ca_pem).unwrap();
+            fs::write(dir.join("client.pem"), &self.client_pem.0).unwrap();
+            fs::write(dir.join("client.key"), &self.client_pem.1).unwrap();
+
+            NodeConfig {
+                endpoint: Some(self.endpoint()),
+                ca_cert: Some(dir.join("ca.pem")),
+                client_cert: with_client_cert.then(|| dir.join("client.pem")),
+                client_key: with_client_cert.then(|| dir.join("client.key")),
+                timeout: Some(5),
+                ..NodeConfig::default()
+            }
+        }
+
+        /// Accepts one connection and answers its request, if the TLS handshake succeeds.
+        async fn serve(self) -> Option<String> {
+            let (stream, _) = self.listener.accept().await.unwrap();
+            let stream = self.acceptor.accept(stream).await.ok()?;
+            Some(respond(stream).await)
+        }
+    }
+
+    #[tokio::test]
+    async fn sends_headers_and_token() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            respond(stream).await
+        });
+
+        let config = NodeConfig {
+            endpoint: Some(format!("http://{address}/graphql")),
+            headers: BTreeMap::from([("X-Project".into(), "fishy".into())]),
+            token_env: Some("FISHY_TEST_TOKEN".into()),
+            timeout: Some(5),
+            ..NodeConfig::default()
+        };
+
+        let client = Client::with_env(&config, |name| {
+            (name == "FISHY_TEST_TOKEN").then(|| "secret-token".into())
+        })
+        .unwrap();
+        let response: Pong = client.query("{ pong }").await.unwrap();
+        assert!(response.pong);
+
+        let request = server.await.unwrap();
+        assert!(request.contains("x-project: fishy"));
+        assert!(request.contains("authorization: bearer secret-token"));
+    }
+
+    #[tokio::test]
+    async fn verifies_node_with_custom_ca_and_client_certificate() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let node = TlsNode::new().await;
+        let config = node.config(dir.path(), true);
+        let server = tokio::spawn(node.serve());
+
+        let client = Client::new(&config).unwrap();
+        let response: Pong = client.query("{ pong }").await.unwrap();
+        assert!(response.pong);
+        assert!(server.await.unwrap().is_some());
+
+        // The node rejects clients without a certificate
+        let node = TlsNode::new().await;
+        let config = node.config(dir.path(), false);
+        let server = tokio::spawn(node.serve());
+
+        let client = Client::new(&config).unwrap();
+        assert!(client.query::<Pong>("{ pong }").await.is_err());
+        assert!(server.await.unwrap().is_none());
+
+        // The client rejects nodes which are not signed by the given CA
+        let node = TlsNode::new().await;
+        let config = NodeConfig {
+            ca_cert: None,
+            ..node.config(dir.path(), true)
+        };
+        let server = tokio::spawn(node.serve());
+
+        let client = Client::new(&config).unwrap();
+        assert!(client.query::<Pong>("{ pong }").await.is_err());
+        assert!(server.await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn times_out() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+
+        // Accepts the connection but never answers
+        let server = tokio::spawn(async move { listener.accept().await.unwrap() });
+
+        let config = NodeConfig {
+            endpoint: Some(format!("http://{address}/graphql")),
+            timeout: Some(1),
+            ..NodeConfig::default()
+        };
+
+        let started = Instant::now();
+        let client = Client::new(&config).unwrap();
+        assert!(client.query::<Pong>("{ pong }").await.is_err());
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        drop(server);
+    }
+}
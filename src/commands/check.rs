@@ -0,0 +1,13 @@
+use anyhow::Result;
+use p2panda_rs::schema::SchemaName;
+
+use crate::check::ensure_valid_schema_file;
+use crate::context::Context;
+
+/// Checks the schema file for problems, relations may point to the given external schemas.
+pub fn check(context: Context, external: &[SchemaName]) -> Result<()> {
+    ensure_valid_schema_file(&context.schema_path, context.schema_format, external)?;
+    println!("No problems found in {}", context.schema_path.display());
+
+    Ok(())
+}
@@ -0,0 +1,217 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{bail, Context as ErrorContext, Result};
+use p2panda_rs::schema::SchemaName;
+
+use crate::check::compile_patterns;
+use crate::context::Context;
+use crate::files::{
+    read_schema_file, FieldType, RelationId, RelationType, SchemaField, SchemaFile,
+};
+
+/// Turns a schema name like `blog_post` into a type name like `BlogPost`.
+fn type_name(schema_name: &SchemaName) -> String {
+    schema_name
+        .to_string()
+        .split('_')
+        .map(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(first) => first.to_ascii_uppercase().to_string() + chars.as_str(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
+
+/// Wraps the text in a JSDoc comment, escaping everything which would end it early.
+fn doc_comment(text: &str) -> String {
+    format!("/** {} */", text.replace("*/", "*\\/"))
+}
+
+/// Returns the TypeScript type of a field and the notes about its constraints.
+fn field_type(field: &SchemaField) -> Result<(String, Vec<String>)> {
+    let typescript_field = match field {
+        SchemaField::Field {
+            field_type,
+            constraints,
+        } => {
+            let mut notes = Vec::new();
+            if let Some(min) = constraints.min {
+                notes.push(format!("@minimum {min}"));
+            }
+            if let Some(max) = constraints.max {
+                notes.push(format!("@maximum {max}"));
+            }
+            if let Some(pattern) = &constraints.pattern {
+                notes.push(format!("@pattern {pattern}"));
+            }
+
+            let typescript_type = match (field_type, &constraints.values) {
+                (FieldType::Boolean, _) => "boolean".to_string(),
+                (FieldType::Float | FieldType::Integer, _) => "number".to_string(),
+                (FieldType::String, Some(values)) => values
+                    .iter()
+                    // JSON strings are valid TypeScript string literals
+                    .map(|value| serde_json::Value::from(value.as_str()).to_string())
+                    .collect::<Vec<String>>()
+                    .join(" | "),
+                (FieldType::String, None) => "string".to_string(),
+            };
+
+            (typescript_type, notes)
+        }
+        SchemaField::Relation { field_type, schema } => {
+            let (typescript_type, description) = match field_type {
+                RelationType::Relation => ("string", "Document id"),
+                RelationType::RelationList => ("string[]", "Document ids"),
+                RelationType::PinnedRelation => ("string[]", "Document view id"),
+                RelationType::PinnedRelationList => ("string[][]", "Document view ids"),
+            };
+
+            let related = match &schema.id {
+                RelationId::Name(name) => name.to_string(),
+                RelationId::Id(id) => id.to_string(),
+            };
+
+            (
+                typescript_type.to_string(),
+                vec![format!("{description} of {related}")],
+            )
+        }
+        SchemaField::Object { .. } => {
+            bail!("Nested fields have to be expanded into a generated schema first")
+        }
+    };
+
+    Ok(typescript_field)
+}
+
+/// Generates a TypeScript interface for the documents of every schema.
+fn typescript(schema_file: &SchemaFile) -> Result<String> {
+    let mut code = String::from("// This file is automatically generated by fishy.\n");
+
+    for (schema_name, schema_item) in schema_file.iter() {
+        code.push('\n');
+
+        let description = schema_item.description.to_string();
+        if !description.is_empty() {
+            code.push_str(&format!("{}\n", doc_comment(&description)));
+        }
+
+        code.push_str(&format!("export interface {} {{\n", type_name(schema_name)));
+
+        for (field_name, field) in schema_item.fields.iter() {
+            let (typescript_type, notes) = field_type(field).with_context(|| {
+                format!("Could not generate field '{field_name}' of schema {schema_name}")
+            })?;
+            let is_optional = matches!(
+                field,
+                SchemaField::Field { constraints, .. } if constraints.optional
+            );
+
+            if !notes.is_empty() {
+                code.push_str(&format!("  {}\n", doc_comment(&notes.join(" "))));
+            }
+
+            code.push_str(&format!(
+                "  {field_name}{}: {typescript_type};\n",
+                if is_optional { "?" } else { "" }
+            ));
+        }
+
+        code.push_str("}\n");
+    }
+
+    Ok(code)
+}
+
+/// Writes TypeScript types of the documents of all schemas, including their constraints.
+pub fn codegen(context: Context, output: &Path, force: bool) -> Result<()> {
+    if output.exists() && !force {
+        bail!(
+            "Found an already existing '{}' file, use --force to overwrite it",
+            output.display()
+        );
+    }
+
+    let schema_file = read_schema_file(&context.schema_path, context.schema_format)?;
+    for (schema_name, schema_item) in schema_file.iter() {
+        compile_patterns(schema_name, schema_item)?;
+    }
+
+    fs::write(output, typescript(&schema_file)?)
+        .with_context(|| format!("Could not write {}", output.display()))?;
+
+    println!(
+        "Generated TypeScript types of {} in {}",
+        context.schema_path.display(),
+        output.display()
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::typescript;
+    use crate::files::SchemaFormat;
+
+    #[test]
+    fn generates_typescript() {
+        let schema_file = SchemaFormat::Toml
+            .deserialize(
+                r#"
+                [blog_post]
+                description = "Blog post"
+
+                [blog_post.fields]
+                status = { type = "str", values = ["draft", "published"] }
+                rating = { type = "int", min = 1, max = 5, optional = true }
+                author = { type = "relation", schema = { name = "author" } }
+                "#,
+            )
+            .unwrap();
+
+        assert_eq!(
+            typescript(&schema_file).unwrap(),
+            r#"// This file is automatically generated by fishy.
+
+/** Blog post */
+export interface BlogPost {
+  /** Document id of author */
+  author: string;
+  /** @minimum 1 @maximum 5 */
+  rating?: number;
+  status: "draft" | "published";
+}
+"#
+        );
+
+        let pattern = SchemaFormat::Toml
+            .deserialize(
+                r#"
+                [path]
+                description = ""
+                fields = { value = { type = "str", pattern = "^.*/$" } }
+                "#,
+            )
+            .unwrap();
+        assert!(typescript(&pattern)
+            .unwrap()
+            .contains("  /** @pattern ^.*\\/$ */\n"));
+
+        // Schema files which were not read with `read_schema_file` can still contain nested fields
+        let nested = SchemaFormat::Toml
+            .deserialize(
+                r#"
+                [user]
+                description = ""
+                fields = { address = { type = "relation", fields = { city = { type = "str" } } } }
+                "#,
+            )
+            .unwrap();
+        assert!(typescript(&nested).is_err());
+    }
+}
@@ -0,0 +1,61 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{bail, Context as ErrorContext, Result};
+use serde::Serialize;
+
+use crate::context::Context;
+use crate::files::{read_schema_definitions, Mixins, SchemaFile, SchemaFormat};
+
+/// Mixins and schemas as they are written in a schema file, before they are expanded.
+#[derive(Serialize)]
+struct Definitions<'a> {
+    #[serde(skip_serializing_if = "Mixins::is_empty")]
+    mixins: &'a Mixins,
+
+    #[serde(flatten)]
+    schemas: &'a SchemaFile,
+}
+
+/// Returns the content of the schema file in another format.
+///
+/// Included files are merged into the converted content, mixins, `extends` and nested fields are
+/// kept as they are.
+pub fn convert_schema_file(path: &Path, format: SchemaFormat, to: SchemaFormat) -> Result<String> {
+    let (mixins, schema_file) = read_schema_definitions(path, format)?;
+
+    to.serialize(&Definitions {
+        mixins: &mixins,
+        schemas: &schema_file,
+    })
+}
+
+/// Writes the schema file in another format.
+///
+/// Included files are merged into the converted file, mixins, `extends` and nested fields are
+/// kept as they are.
+pub fn convert(
+    context: Context,
+    output: &Path,
+    format: Option<SchemaFormat>,
+    force: bool,
+) -> Result<()> {
+    if output.exists() && !force {
+        bail!(
+            "Found an already existing '{}' file, use --force to overwrite it",
+            output.display()
+        );
+    }
+
+    let format = format.unwrap_or_else(|| SchemaFormat::from_path(output));
+    let content = convert_schema_file(&context.schema_path, context.schema_format, format)?;
+    fs::write(output, content).with_context(|| format!("Could not write {}", output.display()))?;
+
+    println!(
+        "Converted {} to {format} in {}",
+        context.schema_path.display(),
+        output.display()
+    );
+
+    Ok(())
+}
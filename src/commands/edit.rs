@@ -0,0 +1,408 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context as ErrorContext, Result};
+use p2panda_rs::schema::{SchemaDescription, SchemaName};
+use serde::Serialize;
+use toml_edit::ser::ValueSerializer;
+use toml_edit::{Document, Item, Table, TableLike};
+
+use crate::check::{is_valid_field_name, FIELD_TYPES, RELATION_TYPES};
+use crate::context::Context;
+use crate::files::{
+    read_schema_definitions, read_schema_file, schema_file_paths, FieldConstraints, RelationId,
+    RelationSchema, SchemaField, SchemaFile, SchemaFormat,
+};
+
+/// Makes sure the schema file can be edited, which is only possible for TOML.
+fn ensure_toml(context: &Context) -> Result<()> {
+    if context.schema_format != SchemaFormat::Toml {
+        bail!(
+            "Only TOML schema files can be edited, {} is {}",
+            context.schema_path.display(),
+            context.schema_format
+        );
+    }
+
+    Ok(())
+}
+
+fn read_document(path: &Path) -> Result<Document> {
+    fs::read_to_string(path)
+        .with_context(|| format!("Could not read {}", path.display()))?
+        .parse()
+        .with_context(|| format!("Invalid {} format", path.display()))
+}
+
+fn write_document(path: &Path, document: &Document) -> Result<()> {
+    // Removing the first table leaves an empty line at the start of the file
+    fs::write(path, document.to_string().trim_start())
+        .with_context(|| format!("Could not write {}", path.display()))
+}
+
+/// Reads the file which defines the schema, either the schema file or one of its includes.
+fn read_defining_document(
+    context: &Context,
+    schema_name: &SchemaName,
+) -> Result<(PathBuf, Document)> {
+    for path in schema_file_paths(&context.schema_path, context.schema_format)? {
+        if SchemaFormat::from_path(&path) != SchemaFormat::Toml {
+            continue;
+        }
+
+        let document = read_document(&path)?;
+        if document.contains_key(&schema_name.to_string()) {
+            return Ok((path, document));
+        }
+    }
+
+    bail!(
+        "Schema {schema_name} is not defined in any TOML file of {}",
+        context.schema_path.display()
+    )
+}
+
+fn is_defined(schema_file: &SchemaFile, schema_name: &SchemaName) -> bool {
+    schema_file.iter().any(|(name, _)| name == schema_name)
+}
+
+/// Builds a field of the given type, relation fields need the related schema.
+fn schema_field(field_type: &str, relation: Option<RelationSchema>) -> Result<SchemaField> {
+    let value = toml::Value::String(field_type.to_string());
+
+    if FIELD_TYPES.contains(&field_type) {
+        if relation.is_some() {
+            bail!("Fields of type '{field_type}' can't relate to a schema");
+        }
+
+        return Ok(SchemaField::Field {
+            field_type: value.try_into()?,
+            constraints: FieldConstraints::default(),
+        });
+    }
+
+    if RELATION_TYPES.contains(&field_type) {
+        let schema = relation.with_context(|| {
+            format!("Fields of type '{field_type}' need a related schema, given with --to or --id")
+        })?;
+
+        return Ok(SchemaField::Relation {
+            field_type: value.try_into()?,
+            schema,
+        });
+    }
+
+    bail!(
+        "Unknown field type '{field_type}', expected one of: {}, {}",
+        FIELD_TYPES.join(", "),
+        RELATION_TYPES.join(", ")
+    )
+}
+
+/// Returns the fields of a schema defined in the document.
+///
+/// Schemas which only extend others don't need own fields, their table is added if missing.
+fn fields_mut<'a>(document: &'a mut Document, schema_name: &str) -> Result<&'a mut dyn TableLike> {
+    let schema = document
+        .get_mut(schema_name)
+        .and_then(Item::as_table_like_mut)
+        .with_context(|| format!("Schema {schema_name} has to be a table"))?;
+
+    if !schema.contains_key("fields") {
+        schema.insert("fields", Item::Table(Table::new()));
+    }
+
+    schema
+        .get_mut("fields")
+        .and_then(Item::as_table_like_mut)
+        .with_context(|| format!("Schema {schema_name} doesn't have a 'fields' table"))
+}
+
+fn insert_field(
+    document: &mut Document,
+    schema_name: &str,
+    field_name: &str,
+    field: &SchemaField,
+) -> Result<()> {
+    let value = field.serialize(ValueSerializer::new())?;
+    let fields = fields_mut(document, schema_name)?;
+
+    if fields.contains_key(field_name) {
+        bail!("Schema {schema_name} already has a field '{field_name}'");
+    }
+
+    fields.insert(field_name, Item::Value(value));
+
+    Ok(())
+}
+
+fn remove_field_from(document: &mut Document, schema_name: &str, field_name: &str) -> Result<()> {
+    let fields = fields_mut(document, schema_name)?;
+
+    if !fields.contains_key(field_name) {
+        bail!("Schema {schema_name} doesn't have a field '{field_name}'");
+    }
+
+    fields.remove(field_name);
+
+    Ok(())
+}
+
+/// Checks the name and type of a field given when adding a schema.
+fn new_schema_field(
+    schema_name: &SchemaName,
+    field_name: &str,
+    field_type: &str,
+) -> Result<SchemaField> {
+    if !is_valid_field_name(field_name) {
+        bail!(
+            "Invalid field name '{field_name}', it has to start with a letter and can only \
+            contain letters, numbers and underscores"
+        );
+    }
+
+    if RELATION_TYPES.contains(&field_type) {
+        bail!(
+            "Relation fields are added with `fishy add field {schema_name} {field_name} \
+            {field_type} --to <SCHEMA>`"
+        );
+    }
+
+    schema_field(field_type, None)
+}
+
+/// Adds a schema with the given fields to the schema file.
+pub fn add_schema(
+    context: Context,
+    schema_name: &SchemaName,
+    description: &str,
+    fields: &[(String, String)],
+) -> Result<()> {
+    ensure_toml(&context)?;
+
+    // Schemas without fields are rejected by `check` and `update`
+    if fields.is_empty() {
+        bail!("Schema {schema_name} needs at least one field");
+    }
+
+    let schema_file = read_schema_file(&context.schema_path, context.schema_format)?;
+    if is_defined(&schema_file, schema_name) {
+        bail!("Schema {schema_name} is already defined");
+    }
+
+    SchemaDescription::new(description).with_context(|| "Invalid description")?;
+
+    let mut schema = Table::new();
+    schema.insert("description", toml_edit::value(description));
+    schema.insert("fields", Item::Table(Table::new()));
+
+    let mut document = read_document(&context.schema_path)?;
+    document.insert(&schema_name.to_string(), Item::Table(schema));
+
+    for (field_name, field_type) in fields {
+        let field = new_schema_field(schema_name, field_name, field_type)?;
+        insert_field(&mut document, &schema_name.to_string(), field_name, &field)?;
+    }
+
+    write_document(&context.schema_path, &document)?;
+
+    println!(
+        "Added schema {schema_name} to {}, add more fields with `fishy add field {schema_name}`",
+        context.schema_path.display()
+    );
+
+    Ok(())
+}
+
+/// Adds a field to a schema, in the file which defines the schema.
+pub fn add_field(
+    context: Context,
+    schema_name: &SchemaName,
+    field_name: &str,
+    field_type: &str,
+    relation: Option<RelationSchema>,
+) -> Result<()> {
+    ensure_toml(&context)?;
+
+    if !is_valid_field_name(field_name) {
+        bail!(
+            "Invalid field name '{field_name}', it has to start with a letter and can only \
+            contain letters, numbers and underscores"
+        );
+    }
+
+    let field = schema_field(field_type, relation)?;
+
+    let schema_file = read_schema_file(&context.schema_path, context.schema_format)?;
+    let (_, schema_item) = schema_file
+        .iter()
+        .find(|(name, _)| *name == schema_name)
+        .with_context(|| format!("Schema {schema_name} is not defined"))?;
+
+    // Inherited fields can't be defined again
+    if schema_item
+        .fields
+        .iter()
+        .any(|(existing_name, _)| existing_name == field_name)
+    {
+        bail!("Schema {schema_name} already has a field '{field_name}'");
+    }
+
+    if let SchemaField::Relation {
+        schema:
+            RelationSchema {
+                id: RelationId::Name(related_name),
+                external: None,
+            },
+        ..
+    } = &field
+    {
+        if !is_defined(&schema_file, related_name) {
+            bail!(
+                "Schema {related_name} is not defined, use --git or --path for schemas of \
+                other projects"
+            );
+        }
+    }
+
+    let (path, mut document) = read_defining_document(&context, schema_name)?;
+    insert_field(&mut document, &schema_name.to_string(), field_name, &field)?;
+    write_document(&path, &document)?;
+
+    println!(
+        "Added field '{field_name}' to schema {schema_name} in {}",
+        path.display()
+    );
+
+    Ok(())
+}
+
+/// Removes a schema which isn't related to or extended by other schemas.
+pub fn remove_schema(context: Context, schema_name: &SchemaName) -> Result<()> {
+    ensure_toml(&context)?;
+
+    let (_, definitions) = read_schema_definitions(&context.schema_path, context.schema_format)?;
+    for (name, schema_item) in definitions.iter() {
+        if schema_item
+            .extends
+            .iter()
+            .any(|extended| *extended == schema_name.to_string())
+        {
+            bail!("Schema {schema_name} is still extended by schema {name}");
+        }
+    }
+
+    let schema_file = read_schema_file(&context.schema_path, context.schema_format)?;
+
+    for (name, schema_item) in schema_file.iter() {
+        for (field_name, field) in schema_item.fields.iter() {
+            if let SchemaField::Relation {
+                schema:
+                    RelationSchema {
+                        id: RelationId::Name(related_name),
+                        external: None,
+                    },
+                ..
+            } = field
+            {
+                if related_name == schema_name && name != schema_name {
+                    bail!(
+                        "Schema {schema_name} is still related to by field '{field_name}' of \
+                        schema {name}"
+                    );
+                }
+            }
+        }
+    }
+
+    let (path, mut document) = read_defining_document(&context, schema_name)?;
+    document.remove(&schema_name.to_string());
+    write_document(&path, &document)?;
+
+    println!("Removed schema {schema_name} from {}", path.display());
+
+    Ok(())
+}
+
+/// Removes a field from a schema, in the file which defines the schema.
+pub fn remove_field(context: Context, schema_name: &SchemaName, field_name: &str) -> Result<()> {
+    ensure_toml(&context)?;
+
+    let (path, mut document) = read_defining_document(&context, schema_name)?;
+    remove_field_from(&mut document, &schema_name.to_string(), field_name)?;
+
+    // Inherited fields count as well, a schema extending others doesn't need own fields
+    let schema_file = read_schema_file(&context.schema_path, context.schema_format)?;
+    let (_, schema_item) = schema_file
+        .iter()
+        .find(|(name, _)| *name == schema_name)
+        .with_context(|| format!("Schema {schema_name} is not defined"))?;
+
+    if schema_item.fields.len() == 1 {
+        bail!("Can't remove the last field of schema {schema_name}, remove the schema instead");
+    }
+
+    write_document(&path, &document)?;
+
+    println!(
+        "Removed field '{field_name}' from schema {schema_name} in {}",
+        path.display()
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use toml_edit::Document;
+
+    use super::{insert_field, new_schema_field, remove_field_from, schema_field};
+    use crate::files::{RelationId, RelationSchema, RelationSource};
+
+    #[test]
+    fn edits_fields() {
+        let mut document: Document = r#"# Blog posts
+[post]
+description = "Blog post"
+
+[post.fields]
+# Shown in the list of posts
+title = { type = "str" }
+body = { type = "str" }
+"#
+        .parse()
+        .unwrap();
+
+        let relation = RelationSchema {
+            id: RelationId::Name("author".parse().unwrap()),
+            external: Some(RelationSource::Path("../users".into())),
+        };
+        let field = schema_field("relation", Some(relation)).unwrap();
+
+        insert_field(&mut document, "post", "author", &field).unwrap();
+        assert!(insert_field(&mut document, "post", "author", &field).is_err());
+        remove_field_from(&mut document, "post", "body").unwrap();
+
+        assert_eq!(
+            document.to_string(),
+            r#"# Blog posts
+[post]
+description = "Blog post"
+
+[post.fields]
+# Shown in the list of posts
+title = { type = "str" }
+author = { type = "relation", schema = { name = "author", path = "../users" } }
+"#
+        );
+
+        assert!(remove_field_from(&mut document, "post", "missing").is_err());
+        assert!(schema_field("relation", None).is_err());
+        assert!(schema_field("string", None).is_err());
+
+        let schema_name = "post".parse().unwrap();
+        assert!(new_schema_field(&schema_name, "title", "str").is_ok());
+        assert!(new_schema_field(&schema_name, "author", "relation").is_err());
+        assert!(new_schema_field(&schema_name, "1st", "str").is_err());
+    }
+}
@@ -0,0 +1,50 @@
+use std::fs;
+
+use anyhow::{bail, Context as ErrorContext, Result};
+
+use crate::context::Context;
+use crate::files::{schema_file_paths, SchemaFormat};
+use crate::formatter::format_schema_toml;
+
+/// Rewrites the schema file and all included TOML files into the canonical layout.
+///
+/// With `check` no file is written. Returns true if all files were formatted already.
+pub fn fmt(context: Context, check: bool) -> Result<bool> {
+    if context.schema_format != SchemaFormat::Toml {
+        bail!(
+            "Only TOML schema files can be formatted, {} is {}",
+            context.schema_path.display(),
+            context.schema_format
+        );
+    }
+
+    let mut is_formatted = true;
+
+    for path in schema_file_paths(&context.schema_path, context.schema_format)? {
+        if SchemaFormat::from_path(&path) != SchemaFormat::Toml {
+            println!("Skipping {}, only TOML files are formatted", path.display());
+            continue;
+        }
+
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Could not read {}", path.display()))?;
+        let formatted = format_schema_toml(&content)
+            .with_context(|| format!("Could not format {}", path.display()))?;
+
+        if formatted == content {
+            continue;
+        }
+
+        is_formatted = false;
+
+        if check {
+            println!("{} is not formatted", path.display());
+        } else {
+            fs::write(&path, formatted)
+                .with_context(|| format!("Could not write {}", path.display()))?;
+            println!("Formatted {}", path.display());
+        }
+    }
+
+    Ok(is_formatted)
+}
@@ -1,38 +1,256 @@
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use anyhow::{bail, Context as ErrorContext, Result};
 use p2panda_rs::identity::KeyPair;
 use p2panda_rs::schema::{SchemaDescription, SchemaName};
+use p2panda_rs::test_utils::memory_store::MemoryStore;
 
+use crate::check::ensure_valid_schema_file;
+use crate::commands::convert::convert_schema_file;
+use crate::commands::update::plan_commits;
 use crate::context::Context;
-use crate::files::{FieldType, SchemaFields, SchemaFile};
+use crate::files::{
+    read_schema_file, schema_file_paths, FieldConstraints, FieldType, SchemaField, SchemaFields,
+    SchemaFile, SchemaFormat,
+};
+use crate::key::{is_encrypted_key_file, write_key_file};
+use crate::store::ExternalSchemas;
 
-fn write_file(path: &str, content: &str) -> Result<()> {
-    let mut file = File::create(&path)?;
+/// Built-in templates for `init --template`.
+const TEMPLATES: [(&str, &str); 3] = [
+    ("blog", include_str!("../../templates/blog.toml")),
+    ("chat", include_str!("../../templates/chat.toml")),
+    ("settings", include_str!("../../templates/settings.toml")),
+];
+
+fn write_file(path: &Path, content: &str) -> Result<()> {
+    let mut file = File::create(path)?;
     file.write_all(content.as_bytes())?;
     Ok(())
 }
 
-pub fn init(_context: Context, name: &str) -> Result<()> {
-    ["schema.toml", "schema.lock", "secret.txt"]
-        .iter()
-        .try_for_each(|file_name| {
-            if Path::new(file_name).exists() {
-                bail!("Found an already existing '{file_name}' file")
+/// Returns the content and format of a built-in template or of a user template, which is either
+/// a schema file or a folder containing one.
+///
+/// User templates also return the path of their schema file.
+fn load_template(template: &str) -> Result<(String, SchemaFormat, Option<PathBuf>)> {
+    let path = Path::new(template);
+
+    let path = if path.is_dir() {
+        SchemaFormat::EXTENSIONS
+            .iter()
+            .map(|extension| path.join(format!("schema.{extension}")))
+            .find(|path| path.is_file())
+            .with_context(|| format!("No schema file found in template {}", path.display()))?
+    } else {
+        path.to_path_buf()
+    };
+
+    if path.is_file() {
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Could not read template {}", path.display()))?;
+        let format = SchemaFormat::from_path(&path);
+        return Ok((content, format, Some(path)));
+    }
+
+    match TEMPLATES.iter().find(|(name, _)| *name == template) {
+        Some((_, content)) => Ok((content.to_string(), SchemaFormat::Toml, None)),
+        None => {
+            let names: Vec<&str> = TEMPLATES.iter().map(|(name, _)| *name).collect();
+            bail!(
+                "Unknown template '{template}', use a path or one of: {}",
+                names.join(", ")
+            )
+        }
+    }
+}
+
+/// Makes sure a template can be used by `update` right away.
+///
+/// Besides checking the schema file, its commits are planned with a throwaway key like `status`
+/// does.
+async fn check_template(path: &Path, format: SchemaFormat) -> Result<()> {
+    ensure_valid_schema_file(path, format, &[])?;
+    let schema_file = read_schema_file(path, format)?;
+
+    let context = Context::new(
+        MemoryStore::default(),
+        &path.to_path_buf(),
+        &path.with_extension("lock"),
+        &path.with_file_name("fishy.toml"),
+        format,
+    );
+    plan_commits(
+        context,
+        &schema_file,
+        &[],
+        &ExternalSchemas::new(),
+        Box::new(KeyPair::new()),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Makes sure the file is listed in the `.gitignore` file of the given folder.
+///
+/// Returns true if the entry had to be added.
+fn add_to_gitignore(directory: &Path, file_name: &str) -> Result<bool> {
+    let gitignore_path = directory.join(".gitignore");
+    let mut content = fs::read_to_string(&gitignore_path).unwrap_or_default();
+
+    let is_ignored = content.lines().any(|line| {
+        let line = line.trim();
+        line == file_name || line == format!("/{file_name}")
+    });
+
+    if is_ignored {
+        return Ok(false);
+    }
+
+    if !content.is_empty() && !content.ends_with('\n') {
+        content.push('\n');
+    }
+    content.push_str(&format!("{file_name}\n"));
+
+    fs::write(&gitignore_path, content).with_context(|| "Could not update .gitignore file")?;
+
+    Ok(true)
+}
+
+pub async fn init(
+    context: Context,
+    directory: Option<&Path>,
+    private_key_path: &Path,
+    name: &str,
+    template: Option<&str>,
+    encrypt: bool,
+    force: bool,
+) -> Result<()> {
+    let directory = directory.unwrap_or(Path::new(""));
+
+    // Relative paths are resolved inside of the target folder
+    let schema_path = directory.join(&context.schema_path);
+    let lock_path = directory.join(&context.lock_path);
+    let private_key_path = directory.join(private_key_path);
+
+    let schema_file_str = match template {
+        Some(template) => match load_template(template)? {
+            (content, format, Some(path)) => {
+                check_template(&path, format)
+                    .await
+                    .with_context(|| format!("Invalid template '{template}'"))?;
+
+                // Keep comments of the template if no conversion or merging of included files is
+                // needed
+                if format == context.schema_format && schema_file_paths(&path, format)?.len() == 1 {
+                    content
+                } else {
+                    convert_schema_file(&path, format, context.schema_format)?
+                }
             }
+            // Built-in templates are checked by the tests
+            (content, _, None) if context.schema_format == SchemaFormat::Toml => content,
+            (content, format, None) => context
+                .schema_format
+                .serialize(&format.deserialize(&content)?)?,
+        },
+        None => default_schema_file(name, context.schema_format)?,
+    };
+
+    if !force {
+        [&schema_path, &lock_path, &private_key_path]
+            .iter()
+            .try_for_each(|path| {
+                if path.exists() {
+                    bail!(
+                        "Found an already existing '{}' file, use --force to overwrite it",
+                        path.display()
+                    )
+                }
+
+                Ok(())
+            })
+            .with_context(|| "Could not initialise schema in this folder")?;
+    }
+
+    // The commits in an existing lock file are signed with the existing key, keep both together
+    let keep_key = force && lock_path.exists();
+    if keep_key {
+        if !private_key_path.exists() {
+            bail!(
+                "Found an already existing '{}' file but no key file '{}' it was signed with, \
+                remove the lock file to start over",
+                lock_path.display(),
+                private_key_path.display()
+            );
+        }
+
+        if encrypt && !is_encrypted_key_file(&private_key_path)? {
+            bail!(
+                "Can't encrypt the existing key file '{}' which is kept together with '{}', \
+                use `fishy key encrypt` for that",
+                private_key_path.display(),
+                lock_path.display()
+            );
+        }
+    }
+
+    // Only create the target folder once everything is known to work out
+    if !directory.as_os_str().is_empty() {
+        fs::create_dir_all(directory)
+            .with_context(|| format!("Could not create folder {}", directory.display()))?;
+    }
 
-            Ok(())
-        })
-        .with_context(|| "Could not initialise schema in this folder")?;
+    if keep_key {
+        println!(
+            "Keeping existing {} and {}, they still contain the previously created schemas",
+            lock_path.display(),
+            private_key_path.display()
+        );
+    } else {
+        if force && private_key_path.exists() {
+            fs::remove_file(&private_key_path).with_context(|| {
+                format!("Could not remove key file {}", private_key_path.display())
+            })?;
+        }
 
-    let key_pair = KeyPair::new();
+        let key_pair = KeyPair::new();
+        write_key_file(&private_key_path, &key_pair, encrypt)?;
+    }
 
-    let private_key_file_str = hex::encode(key_pair.private_key());
-    write_file("secret.txt", &private_key_file_str)
-        .with_context(|| "Could not create secret.txt file")?;
+    // Key files outside of the target folder can't be ignored by its .gitignore
+    if let Some(relative_path) = private_key_path
+        .strip_prefix(directory)
+        .ok()
+        .filter(|path| path.is_relative())
+    {
+        let file_name = relative_path.to_string_lossy().replace('\\', "/");
+        if add_to_gitignore(directory, &file_name)? {
+            println!("Added {file_name} to .gitignore");
+        }
+    }
 
+    write_file(&schema_path, &schema_file_str)
+        .with_context(|| format!("Could not create {} file", schema_path.display()))?;
+
+    if keep_key {
+        println!("Generated template {} file", schema_path.display());
+    } else {
+        println!(
+            "Generated and stored private key in {} and template {} file",
+            private_key_path.display(),
+            schema_path.display()
+        );
+    }
+
+    Ok(())
+}
+
+/// Schema file with a single schema and field, used when no template was chosen.
+fn default_schema_file(name: &str, format: SchemaFormat) -> Result<String> {
     let schema_name =
         SchemaName::new(name).with_context(|| format!("Invalid schema name: '{name}'"))?;
     let schema_description = SchemaDescription::new("")?;
@@ -40,19 +258,212 @@ pub fn init(_context: Context, name: &str) -> Result<()> {
     let mut schema_fields = SchemaFields::new();
     schema_fields.insert(
         "my_field".to_string(),
-        crate::files::SchemaField::Field {
+        SchemaField::Field {
             field_type: FieldType::String,
+            constraints: FieldConstraints::default(),
         },
     );
 
     let mut schema_file = SchemaFile::new();
     schema_file.add_schema(&schema_name, &schema_description, &schema_fields);
 
-    let schema_file_str = toml::to_string_pretty(&schema_file)?;
-    write_file("schema.toml", &schema_file_str)
-        .with_context(|| "Could not create schema.toml file")?;
+    format.serialize(&schema_file)
+}
 
-    println!("Generated and stored private key in secret.txt and template schema.toml file");
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::{Path, PathBuf};
 
-    Ok(())
+    use p2panda_rs::test_utils::memory_store::MemoryStore;
+
+    use super::{add_to_gitignore, check_template, init, TEMPLATES};
+    use crate::context::Context;
+    use crate::files::SchemaFormat;
+
+    fn context() -> Context {
+        Context::new(
+            MemoryStore::default(),
+            &PathBuf::from("schema.toml"),
+            &PathBuf::from("schema.lock"),
+            &PathBuf::from("fishy.toml"),
+            SchemaFormat::Toml,
+        )
+    }
+
+    #[tokio::test]
+    async fn built_in_templates() {
+        let dir = tempfile::tempdir().unwrap();
+
+        for (name, content) in TEMPLATES {
+            let path = dir.path().join(format!("{name}.toml"));
+            fs::write(&path, content).unwrap();
+            assert!(
+                check_template(&path, SchemaFormat::Toml).await.is_ok(),
+                "template {name}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn user_templates() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("schema.toml");
+
+        fs::write(
+            dir.path().join("comment.toml"),
+            r#"
+            [comment]
+            description = "Comment on a post"
+            fields.text = { type = "str" }
+            fields.post = { type = "relation", schema = { name = "post" } }
+            "#,
+        )
+        .unwrap();
+        fs::write(
+            &path,
+            r#"
+            include = ["comment.toml"]
+
+            [mixins.timestamps]
+            created_at = { type = "int" }
+
+            [post]
+            description = "Blog post"
+            extends = "timestamps"
+            fields.title = { type = "str" }
+            fields.location = { type = "relation", fields = { lat = { type = "float" } } }
+            "#,
+        )
+        .unwrap();
+        assert!(check_template(&path, SchemaFormat::Toml).await.is_ok());
+
+        fs::write(
+            &path,
+            r#"
+            [post]
+            description = ""
+            fields.author = { type = "relation", schema = { name = "author" } }
+            "#,
+        )
+        .unwrap();
+        assert!(check_template(&path, SchemaFormat::Toml).await.is_err());
+    }
+
+    #[test]
+    fn adds_key_files_to_gitignore() {
+        let dir = tempfile::tempdir().unwrap();
+        let gitignore_path = dir.path().join(".gitignore");
+
+        assert!(add_to_gitignore(dir.path(), "secret.txt").unwrap());
+        assert_eq!(fs::read_to_string(&gitignore_path).unwrap(), "secret.txt\n");
+
+        // Entries are only added once
+        assert!(!add_to_gitignore(dir.path(), "secret.txt").unwrap());
+
+        fs::write(&gitignore_path, "target\n/keys/secret.txt").unwrap();
+        assert!(!add_to_gitignore(dir.path(), "keys/secret.txt").unwrap());
+
+        assert!(add_to_gitignore(dir.path(), "secret.txt").unwrap());
+        assert_eq!(
+            fs::read_to_string(&gitignore_path).unwrap(),
+            "target\n/keys/secret.txt\nsecret.txt\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn initialises_target_folder() {
+        let dir = tempfile::tempdir().unwrap();
+        let directory = dir.path().join("app");
+        let key_path = Path::new("secret.txt");
+        let read_key = || fs::read_to_string(directory.join(key_path)).unwrap();
+
+        // Nothing is created if the arguments are invalid
+        assert!(init(
+            context(),
+            Some(&directory),
+            key_path,
+            "post",
+            Some("unknown"),
+            false,
+            false
+        )
+        .await
+        .is_err());
+        assert!(!directory.exists());
+
+        init(
+            context(),
+            Some(&directory),
+            key_path,
+            "post",
+            None,
+            false,
+            false,
+        )
+        .await
+        .unwrap();
+        for file_name in ["schema.toml", "secret.txt", ".gitignore"] {
+            assert!(directory.join(file_name).is_file(), "{file_name}");
+        }
+        assert!(!dir.path().join("schema.toml").exists());
+
+        // Existing files are only replaced with --force
+        assert!(init(
+            context(),
+            Some(&directory),
+            key_path,
+            "post",
+            None,
+            false,
+            false
+        )
+        .await
+        .is_err());
+
+        // Without a lock file nothing was signed with the key yet, so it is replaced as well
+        let key = read_key();
+        init(
+            context(),
+            Some(&directory),
+            key_path,
+            "post",
+            None,
+            false,
+            true,
+        )
+        .await
+        .unwrap();
+        assert_ne!(read_key(), key);
+
+        // The key is kept together with the lock file signed with it
+        let key = read_key();
+        fs::write(directory.join("schema.lock"), "").unwrap();
+        init(
+            context(),
+            Some(&directory),
+            key_path,
+            "post",
+            None,
+            false,
+            true,
+        )
+        .await
+        .unwrap();
+        assert_eq!(read_key(), key);
+
+        // A kept key can't be encrypted on the side
+        assert!(init(
+            context(),
+            Some(&directory),
+            key_path,
+            "post",
+            None,
+            true,
+            true
+        )
+        .await
+        .is_err());
+        assert_eq!(read_key(), key);
+    }
 }
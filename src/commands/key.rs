@@ -0,0 +1,137 @@
+use std::fs;
+use std::io::{self, IsTerminal, Read};
+use std::path::Path;
+
+use anyhow::{bail, Context as ErrorContext, Result};
+use p2panda_rs::identity::KeyPair;
+
+use crate::key::{
+    is_encrypted_key_file, key_file_content, key_pair_to_mnemonic, mnemonic_to_key_pair,
+    parse_private_key, read_key_file, store_key_file, write_key_file,
+};
+
+/// Generates a new private key and stores it in a file.
+pub fn key_generate(path: &Path, encrypt: bool) -> Result<()> {
+    let key_pair = KeyPair::new();
+    write_key_file(path, &key_pair, encrypt)?;
+
+    println!("Generated and stored private key in {}", path.display());
+    println!("Public key: {}", key_pair.public_key());
+
+    Ok(())
+}
+
+/// Prints the public key of a key file.
+pub fn key_show(path: &Path) -> Result<()> {
+    let key_pair = read_key_file(path)?;
+    println!("{}", key_pair.public_key());
+
+    Ok(())
+}
+
+/// Validates a hex encoded private key and stores it in a file.
+///
+/// The key is read from the given file or from stdin, so it doesn't end up in the shell history.
+pub fn key_import(source: Option<&Path>, path: &Path, encrypt: bool) -> Result<()> {
+    let private_key = match source {
+        Some(source) => fs::read_to_string(source)
+            .with_context(|| format!("Could not read private key from {}", source.display()))?,
+        None => {
+            let mut buf = String::new();
+            io::stdin()
+                .read_to_string(&mut buf)
+                .with_context(|| "Could not read private key from stdin")?;
+            buf
+        }
+    };
+
+    let key_pair = parse_private_key(&private_key).with_context(|| "Could not import key")?;
+    write_key_file(path, &key_pair, encrypt)?;
+
+    println!("Imported private key into {}", path.display());
+    println!("Public key: {}", key_pair.public_key());
+
+    Ok(())
+}
+
+/// Writes only the public key of a key file, to share it with others.
+pub fn key_export_public(path: &Path, output: Option<&Path>) -> Result<()> {
+    let key_pair = read_key_file(path)?;
+    let public_key = key_pair.public_key().to_string();
+
+    match output {
+        Some(output) => {
+            fs::write(output, &public_key)
+                .with_context(|| format!("Could not write public key to {}", output.display()))?;
+            println!("Exported public key to {}", output.display());
+        }
+        None => println!("{public_key}"),
+    }
+
+    Ok(())
+}
+
+/// Replaces a plaintext key file with one encrypted by a passphrase.
+pub fn key_encrypt(path: &Path) -> Result<()> {
+    if is_encrypted_key_file(path)? {
+        bail!("Key file {} is already encrypted", path.display());
+    }
+
+    // The permissions are not checked here, storing the encrypted key restricts them anyway
+    let private_key = fs::read_to_string(path)
+        .with_context(|| format!("Could not read key file {}", path.display()))?;
+    let key_pair = parse_private_key(&private_key)
+        .with_context(|| format!("Invalid key file {}", path.display()))?;
+    let content = key_file_content(&key_pair, true)?;
+
+    store_key_file(path, &content, true)?;
+
+    println!("Encrypted private key in {}", path.display());
+
+    Ok(())
+}
+
+/// Prints the private key as a mnemonic phrase to write it down as a backup.
+pub fn key_export_mnemonic(path: &Path) -> Result<()> {
+    let key_pair = read_key_file(path)?;
+    let phrase = key_pair_to_mnemonic(&key_pair)?;
+
+    eprintln!(
+        "Anyone with these words can sign schemas as {}.",
+        key_pair.public_key()
+    );
+    eprintln!("Write them down on paper and keep them in a safe place:\n");
+
+    for (index, word) in phrase.split(' ').enumerate() {
+        println!("{:>2}. {word}", index + 1);
+    }
+
+    Ok(())
+}
+
+/// Restores a key file from a mnemonic phrase read from stdin.
+pub fn key_restore_mnemonic(path: &Path, encrypt: bool) -> Result<()> {
+    let phrase = if io::stdin().is_terminal() {
+        rpassword::prompt_password("Mnemonic phrase: ")?
+    } else {
+        let mut buf = String::new();
+        io::stdin()
+            .read_to_string(&mut buf)
+            .with_context(|| "Could not read mnemonic phrase from stdin")?;
+        buf
+    };
+
+    // Allow pasting the numbered list printed by `key export-mnemonic`
+    let phrase: Vec<&str> = phrase
+        .split_whitespace()
+        .filter(|word| !word.ends_with('.'))
+        .collect();
+
+    let key_pair = mnemonic_to_key_pair(&phrase.join(" "))?;
+    write_key_file(path, &key_pair, encrypt)?;
+
+    println!("Restored private key into {}", path.display());
+    println!("Public key: {}", key_pair.public_key());
+
+    Ok(())
+}
@@ -1,7 +1,36 @@
+mod check;
+mod codegen;
+mod convert;
+mod edit;
+mod fmt;
 mod init;
+mod key;
+mod offline;
 mod publish;
+mod serve;
+mod signer;
+mod status;
 mod update;
+mod validate;
+mod workspace;
 
+pub use check::check;
+pub use codegen::codegen;
+pub use convert::convert;
+pub use edit::{add_field, add_schema, remove_field, remove_schema};
+pub use fmt::fmt;
 pub use init::init;
+pub use key::{
+    key_encrypt, key_export_mnemonic, key_export_public, key_generate, key_import,
+    key_restore_mnemonic, key_show,
+};
+pub use offline::{offline_export, offline_import, offline_sign};
 pub use publish::publish;
+pub use serve::serve;
+pub use signer::signer;
+pub use status::status;
 pub use update::update;
+pub use validate::validate;
+pub use workspace::{
+    workspace_check, workspace_fmt, workspace_publish, workspace_status, workspace_update,
+};
@@ -0,0 +1,327 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{bail, Context as ErrorContext, Result};
+use p2panda_rs::entry::decode::decode_entry;
+use p2panda_rs::entry::traits::{AsEncodedEntry, AsEntry};
+use p2panda_rs::identity::{KeyPair, PublicKey};
+use p2panda_rs::test_utils::memory_store::MemoryStore;
+
+use crate::commands::update::{plan_resolved_commits, resolve_external};
+use crate::context::Context;
+use crate::files::{read_lock_file, read_schema_file, write_lock_file, PlanFile};
+use crate::operations::{describe_commits, print_inherited_fields, print_operations};
+use crate::signer::Signer;
+use crate::store::{build_lock_file, ExternalSchemas};
+
+/// Version of the plan file format.
+const PLAN_VERSION: u64 = 1;
+
+/// Context with an empty store, to plan or verify commits independently of each other.
+fn fresh_context(context: &Context) -> Context {
+    Context::new(
+        MemoryStore::default(),
+        &context.schema_path,
+        &context.lock_path,
+        &context.config_path,
+        context.schema_format,
+    )
+}
+
+fn read_plan_file(path: &Path) -> Result<PlanFile> {
+    let plan_file_str = fs::read_to_string(path)
+        .with_context(|| format!("Could not read plan file {}", path.display()))?;
+    let plan_file: PlanFile = toml::from_str(&plan_file_str)
+        .with_context(|| format!("Invalid plan file {}", path.display()))?;
+
+    if plan_file.version != PLAN_VERSION {
+        bail!("Unsupported plan file version {}", plan_file.version);
+    }
+
+    Ok(plan_file)
+}
+
+fn write_plan_file(path: &Path, plan_file: &PlanFile) -> Result<()> {
+    let plan_file_str = format!(
+        "{}\n\n{}",
+        "# This file is automatically generated by fishy.\n# It is not intended for manual editing.",
+        toml::to_string_pretty(plan_file)?
+    );
+
+    fs::write(path, plan_file_str)
+        .with_context(|| format!("Could not write plan file {}", path.display()))
+}
+
+/// Exports everything needed to sign the pending schema changes on another machine.
+pub async fn offline_export(
+    context: Context,
+    public_key: Option<&str>,
+    output: &Path,
+) -> Result<()> {
+    let schema_file = read_schema_file(&context.schema_path, context.schema_format)?;
+
+    let commits = read_lock_file(&context.lock_path)?.commits;
+
+    // Without a given public key we assume the author of the existing commits
+    let public_key = match (public_key, commits.first()) {
+        (Some(public_key), _) => PublicKey::new(public_key)
+            .with_context(|| "Invalid public key")?
+            .to_string(),
+        (None, Some(commit)) => decode_entry(&commit.entry)?.public_key().to_string(),
+        (None, None) => bail!("Unknown author, please pass the public key with --public-key"),
+    };
+
+    // The offline machine can't look up related schemas in other lock files
+    let external = resolve_external(&context, &schema_file, &ExternalSchemas::new()).await?;
+
+    // Plan the commits with a throwaway key to see which operations will need to be signed
+    let simulated = plan_resolved_commits(
+        fresh_context(&context),
+        &schema_file,
+        &commits,
+        &external,
+        Box::new(KeyPair::new()),
+    )
+    .await?;
+
+    if simulated.is_empty() {
+        println!(
+            "Nothing to sign, {} is up to date",
+            context.lock_path.display()
+        );
+        return Ok(());
+    }
+
+    let plan_file = PlanFile {
+        version: PLAN_VERSION,
+        public_key,
+        operations: describe_commits(&simulated)?,
+        external: external.into_iter().collect(),
+        schema: schema_file,
+        commits,
+        signed_commits: None,
+    };
+
+    write_plan_file(output, &plan_file)?;
+
+    println!(
+        "Exported plan with {} operations to {}",
+        plan_file.operations.len(),
+        output.display()
+    );
+    print_operations(&plan_file.operations);
+    print_inherited_fields(&context, &plan_file.operations)?;
+
+    Ok(())
+}
+
+/// Signs the operations of a plan file and stores the resulting commits in it.
+pub async fn offline_sign(
+    context: Context,
+    plan_path: &Path,
+    mut signer: Box<dyn Signer>,
+    output: &Path,
+) -> Result<()> {
+    let mut plan_file = read_plan_file(plan_path)?;
+
+    if plan_file.signed_commits.is_some() {
+        bail!("Plan {} is already signed", plan_path.display());
+    }
+
+    let public_key = signer.public_key()?.to_string();
+    if public_key != plan_file.public_key {
+        bail!(
+            "Plan expects to be signed by {} but the key belongs to {}",
+            plan_file.public_key,
+            public_key
+        );
+    }
+
+    println!("Signing {} operations:", plan_file.operations.len());
+    print_operations(&plan_file.operations);
+
+    let signed_commits = plan_resolved_commits(
+        fresh_context(&context),
+        &plan_file.schema,
+        &plan_file.commits,
+        &plan_file.external.clone().into_iter().collect(),
+        signer,
+    )
+    .await?;
+
+    if describe_commits(&signed_commits)? != plan_file.operations {
+        bail!("Signed commits do not match the operations of the plan");
+    }
+
+    plan_file.signed_commits = Some(signed_commits);
+    write_plan_file(output, &plan_file)?;
+
+    println!("Stored signed commits in {}", output.display());
+
+    Ok(())
+}
+
+/// Verifies the signed commits of a plan file and adds them to the lock file.
+pub async fn offline_import(context: Context, plan_path: &Path) -> Result<()> {
+    let plan_file = read_plan_file(plan_path)?;
+
+    let signed_commits = plan_file
+        .signed_commits
+        .as_ref()
+        .with_context(|| format!("Plan {} has not been signed yet", plan_path.display()))?;
+
+    let mut commits = read_lock_file(&context.lock_path)?.commits;
+
+    let is_same_base = commits.len() == plan_file.commits.len()
+        && commits
+            .iter()
+            .zip(&plan_file.commits)
+            .all(|(current, planned)| current.entry_hash == planned.entry_hash);
+
+    if !is_same_base {
+        bail!(
+            "{} has changed since the plan was exported, please export a new plan",
+            context.lock_path.display()
+        );
+    }
+
+    let schema_file = read_schema_file(&context.schema_path, context.schema_format)?;
+
+    if schema_file != plan_file.schema {
+        bail!(
+            "{} has changed since the plan was exported, please export a new plan",
+            context.schema_path.display()
+        );
+    }
+
+    let external = resolve_external(&context, &schema_file, &ExternalSchemas::new()).await?;
+    if external.clone().into_iter().collect::<BTreeMap<_, _>>() != plan_file.external {
+        bail!("Related schemas have changed since the plan was exported, please export a new plan");
+    }
+
+    for commit in signed_commits {
+        let entry = decode_entry(&commit.entry)
+            .with_context(|| format!("Invalid signed entry {}", commit.entry_hash))?;
+
+        if entry.public_key().to_string() != plan_file.public_key {
+            bail!(
+                "Commit {} was not signed by {}",
+                commit.entry_hash,
+                plan_file.public_key
+            );
+        }
+
+        if commit.entry.hash() != commit.entry_hash {
+            bail!("Commit {} has an invalid entry hash", commit.entry_hash);
+        }
+    }
+
+    if describe_commits(signed_commits)? != plan_file.operations {
+        bail!("Signed commits do not match the operations of the plan");
+    }
+
+    commits.extend(signed_commits.iter().cloned());
+
+    // Replaying all commits must succeed and leave nothing else to do for the schema file
+    let remaining = plan_resolved_commits(
+        fresh_context(&context),
+        &schema_file,
+        &commits,
+        &external,
+        Box::new(KeyPair::new()),
+    )
+    .await
+    .with_context(|| "Signed commits could not be verified")?;
+
+    if !remaining.is_empty() {
+        bail!("Signed commits do not result in the planned schemas");
+    }
+
+    println!(
+        "Verified and imported {} signed commits",
+        signed_commits.len()
+    );
+
+    write_lock_file(&context.lock_path, &build_lock_file(commits).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use p2panda_rs::identity::KeyPair;
+    use p2panda_rs::test_utils::memory_store::MemoryStore;
+
+    use super::{offline_export, offline_import, offline_sign};
+    use crate::commands::status::status;
+    use crate::context::Context;
+    use crate::files::{read_lock_file, SchemaFormat};
+    use crate::store::ExternalSchemas;
+
+    #[tokio::test]
+    async fn signs_plans_on_another_machine() {
+        let dir = tempfile::tempdir().unwrap();
+        let lock_path = dir.path().join("custom.lock");
+        let plan_path = dir.path().join("plan.toml");
+        let signed_path = dir.path().join("signed.toml");
+
+        let schema_path = dir.path().join("schema.toml");
+        fs::write(
+            &schema_path,
+            r#"
+            [post]
+            description = "Blog post"
+            fields = { title = { type = "str" } }
+            "#,
+        )
+        .unwrap();
+
+        let context = Context::new(
+            MemoryStore::default(),
+            &schema_path,
+            &lock_path,
+            &dir.path().join("fishy.toml"),
+            SchemaFormat::Toml,
+        );
+
+        let key_pair = KeyPair::new();
+        let public_key = key_pair.public_key().to_string();
+
+        offline_export(context.clone(), Some(&public_key), &plan_path)
+            .await
+            .unwrap();
+
+        // Only the expected author can sign the plan
+        assert!(offline_sign(
+            context.clone(),
+            &plan_path,
+            Box::new(KeyPair::new()),
+            &signed_path
+        )
+        .await
+        .is_err());
+        offline_sign(
+            context.clone(),
+            &plan_path,
+            Box::new(key_pair),
+            &signed_path,
+        )
+        .await
+        .unwrap();
+
+        assert!(offline_import(context.clone(), &plan_path).await.is_err());
+        offline_import(context.clone(), &signed_path).await.unwrap();
+
+        assert_eq!(read_lock_file(&lock_path).unwrap().commits.len(), 2);
+        assert!(status(context.clone(), &ExternalSchemas::new())
+            .await
+            .unwrap());
+
+        // The plan was exported for the previous state of the lock file
+        let err = offline_import(context, &signed_path).await.unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with(&format!("{} has changed", lock_path.display())));
+    }
+}
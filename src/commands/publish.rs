@@ -1,16 +1,28 @@
-use std::fs;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::time::Instant;
 
-use anyhow::{bail, Result};
-use gql_client::Client;
+use anyhow::{anyhow, bail, Context as ErrorContext, Error, Result};
+use futures::future::try_join_all;
+use futures::stream::{self, StreamExt, TryStreamExt};
 use indicatif::ProgressBar;
 use p2panda_rs::entry::decode::decode_entry;
 use p2panda_rs::entry::traits::AsEntry;
-use p2panda_rs::entry::{LogId, SeqNum};
+use p2panda_rs::entry::{Entry, LogId, SeqNum};
 use p2panda_rs::hash::Hash;
+use p2panda_rs::identity::PublicKey;
+use p2panda_rs::operation::decode::decode_operation;
+use p2panda_rs::operation::plain::PlainValue;
+use p2panda_rs::operation::traits::{Actionable, Schematic};
+use p2panda_rs::operation::OperationAction;
+use p2panda_rs::schema::{SchemaId, SchemaName};
 use serde::Deserialize;
 
+use crate::client::{Client, GraphQLErrors};
 use crate::context::Context;
-use crate::files::LockFile;
+use crate::files::{read_lock_file, Commit, NodeConfig, PlannedOperation};
+use crate::operations::describe_commits;
+use crate::workspace::read_config_file;
 
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
@@ -27,7 +39,7 @@ struct NextArguments {
 #[serde(rename_all = "camelCase")]
 #[allow(dead_code)]
 struct NextArgsResponse {
-    next_args: NextArguments,
+    next_args: Option<NextArguments>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -37,85 +49,1049 @@ struct PublishResponse {
     publish: NextArguments,
 }
 
-pub async fn publish(context: Context, endpoint: &str) -> Result<()> {
-    let lock_file_str = fs::read_to_string(&context.lock_path)?;
-    let lock_file: LockFile = toml::from_str(&lock_file_str)?;
-    let commits = lock_file.commits.unwrap_or(Vec::new());
+/// Maximum number of `publish` mutations which are sent to the node at the same time.
+const MAX_CONCURRENT_REQUESTS: usize = 16;
+
+/// Entry hashes of all commits in the lock file, indexed by author, log id and sequence number.
+type LockEntries = HashMap<(PublicKey, LogId, SeqNum), Hash>;
+
+/// Explains how to get out of a situation where the node and the lock file disagree.
+fn recovery_hint(lock_path: &Path) -> String {
+    let lock_path = lock_path.display();
+
+    format!(
+        "How to recover:
+  - If {lock_path} is outdated or was lost, restore the latest version from version control and
+    run `fishy publish` again.
+  - If the node contains entries which were signed with this key elsewhere, they can't be
+    reconciled with {lock_path} anymore. Create a new key with `fishy init` in a fresh folder
+    and publish the schemas under this new author instead.
+  - If this is a development node, reset its database and publish again."
+    )
+}
+
+fn fork_error(
+    lock_path: &Path,
+    public_key: &PublicKey,
+    log_id: &LogId,
+    seq_num: &SeqNum,
+    lock_hash: Option<&Hash>,
+    node_hash: &Hash,
+) -> Error {
+    let lock_hash = lock_hash
+        .map(|hash| hash.to_string())
+        .unwrap_or("<missing>".into());
+
+    anyhow!(
+        "Fork detected in log {} of author {} at seq num {}:\n\n  \
+        lock file: {}\n  \
+        node:      {}\n\n\
+        The node holds a different entry than {} at this position.\n\n{}",
+        log_id.as_u64(),
+        public_key,
+        seq_num.as_u64(),
+        lock_hash,
+        node_hash,
+        lock_path.display(),
+        recovery_hint(lock_path),
+    )
+}
+
+/// Compares the latest entry the node knows about in this log with the one at the same position
+/// in the lock file.
+///
+/// Since every entry links back to its predecessor by hash, the whole log is identical as soon as
+/// the latest entries match.
+fn check_divergence(
+    lock_path: &Path,
+    lock_entries: &LockEntries,
+    public_key: &PublicKey,
+    log_id: &LogId,
+    args: &NextArguments,
+) -> Result<()> {
+    if log_id != &args.log_id {
+        bail!(
+            "Divergence detected for author {}: {} places this document in log {} but the node \
+            uses log {}.\n\n{}",
+            public_key,
+            lock_path.display(),
+            log_id.as_u64(),
+            args.log_id.as_u64(),
+            recovery_hint(lock_path),
+        );
+    }
+
+    let (node_seq_num, node_hash) = match (args.seq_num.backlink_seq_num(), &args.backlink) {
+        (Some(seq_num), Some(hash)) => (seq_num, hash),
+        // The node does not know any entries in this log yet
+        _ => return Ok(()),
+    };
+
+    let lock_hash = lock_entries.get(&(public_key.clone(), *log_id, node_seq_num));
+
+    if lock_hash != Some(node_hash) {
+        return Err(fork_error(
+            lock_path,
+            public_key,
+            log_id,
+            &node_seq_num,
+            lock_hash,
+            node_hash,
+        ));
+    }
+
+    Ok(())
+}
+
+/// Looks up the name of the schema each schema and field definition belongs to.
+///
+/// Field definitions are attributed to the schema which lists them in its `fields`.
+fn schema_names(
+    commits: &[Commit],
+    operations: &[PlannedOperation],
+) -> Result<HashMap<Hash, String>> {
+    let mut schema_names: HashMap<Hash, String> = HashMap::new();
+
+    for (commit, planned) in commits.iter().zip(operations) {
+        if planned.kind != "schema" {
+            continue;
+        }
+
+        schema_names.insert(commit.entry_hash.clone(), planned.name.clone());
+
+        let operation = decode_operation(&commit.operation)?;
+        if let Some(PlainValue::PinnedRelationList(field_view_ids)) = operation
+            .fields()
+            .as_ref()
+            .and_then(|fields| fields.get("fields"))
+        {
+            for field_hash in field_view_ids.iter().flatten() {
+                schema_names.insert(field_hash.parse()?, planned.name.clone());
+            }
+        }
+    }
+
+    Ok(schema_names)
+}
+
+/// Extracts the id of the related schema from a field type like `relation(<schema_id>)`.
+fn related_schema_id(field_type: &str) -> Option<SchemaId> {
+    let (_, schema_id) = field_type.strip_suffix(')')?.split_once('(')?;
+    schema_id.parse().ok()
+}
+
+/// Selects the commits needed to publish the given schemas, including the commits of all field
+/// definitions and related schemas they depend on.
+///
+/// The order of the lock file is kept, which already is a valid publishing order. Also returns
+/// the related schemas which are not part of the lock file and need to be published on their own.
+pub fn select_commits(
+    lock_path: &Path,
+    commits: Vec<Commit>,
+    schemas: &[SchemaName],
+) -> Result<(Vec<Commit>, Vec<SchemaId>)> {
+    // Every author log contains exactly one document, group the commits accordingly
+    let mut logs: HashMap<(PublicKey, LogId), Vec<usize>> = HashMap::new();
+    let mut log_by_hash: HashMap<Hash, (PublicKey, LogId)> = HashMap::new();
+    let mut schema_logs: HashMap<SchemaName, Vec<(PublicKey, LogId)>> = HashMap::new();
+    let mut operations = Vec::new();
+
+    for (index, commit) in commits.iter().enumerate() {
+        let entry = decode_entry(&commit.entry)?;
+        let operation = decode_operation(&commit.operation)?;
+        let log = (entry.public_key().clone(), *entry.log_id());
+
+        if let (SchemaId::SchemaDefinition(_), OperationAction::Create) =
+            (operation.schema_id(), operation.action())
+        {
+            if let Some(PlainValue::StringOrRelation(name)) = operation
+                .fields()
+                .as_ref()
+                .and_then(|fields| fields.get("name"))
+            {
+                let schema_name = SchemaName::new(name)?;
+                schema_logs
+                    .entry(schema_name)
+                    .or_default()
+                    .push(log.clone());
+            }
+        }
+
+        logs.entry(log.clone()).or_default().push(index);
+        log_by_hash.insert(commit.entry_hash.clone(), log);
+        operations.push(operation);
+    }
+
+    let mut selected: HashSet<(PublicKey, LogId)> = HashSet::new();
+    let mut external: Vec<SchemaId> = Vec::new();
+    let mut visited: HashSet<SchemaName> = HashSet::new();
+    let mut queue: Vec<SchemaName> = schemas.to_vec();
+
+    while let Some(schema_name) = queue.pop() {
+        if !visited.insert(schema_name.clone()) {
+            continue;
+        }
+
+        let schema_log_keys = schema_logs.get(&schema_name).with_context(|| {
+            format!(
+                "Schema '{schema_name}' not found in {}",
+                lock_path.display()
+            )
+        })?;
+
+        for schema_log in schema_log_keys {
+            selected.insert(schema_log.clone());
+
+            for index in &logs[schema_log] {
+                let field_view_ids = match operations[*index]
+                    .fields()
+                    .as_ref()
+                    .and_then(|fields| fields.get("fields"))
+                {
+                    Some(PlainValue::PinnedRelationList(field_view_ids)) => field_view_ids.clone(),
+                    _ => continue,
+                };
+
+                for field_hash in field_view_ids.iter().flatten() {
+                    let field_log = log_by_hash.get(&field_hash.parse()?).with_context(|| {
+                        format!(
+                            "Field {field_hash} of schema '{schema_name}' missing in {}",
+                            lock_path.display()
+                        )
+                    })?;
+
+                    selected.insert(field_log.clone());
+
+                    for field_index in &logs[field_log] {
+                        if let Some(PlainValue::StringOrRelation(field_type)) = operations
+                            [*field_index]
+                            .fields()
+                            .as_ref()
+                            .and_then(|fields| fields.get("type"))
+                        {
+                            // Schemas of other lock files are published on their own
+                            match related_schema_id(field_type) {
+                                Some(related_schema)
+                                    if schema_logs.contains_key(&related_schema.name()) =>
+                                {
+                                    queue.push(related_schema.name())
+                                }
+                                Some(related_schema) if !external.contains(&related_schema) => {
+                                    external.push(related_schema)
+                                }
+                                _ => (),
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    let selected_commits = commits
+        .into_iter()
+        .filter(|commit| selected.contains(&log_by_hash[&commit.entry_hash]))
+        .collect();
+
+    Ok((selected_commits, external))
+}
+
+/// All commits of one author log, in the order of the lock file.
+///
+/// Every author log contains exactly one document.
+struct Log {
+    public_key: PublicKey,
+    log_id: LogId,
+    commits: Vec<(Entry, Commit)>,
+}
+
+fn group_by_log(lock_path: &Path, commits: Vec<Commit>) -> Result<Vec<Log>> {
+    let mut logs: Vec<Log> = Vec::new();
+
+    for commit in commits {
+        let entry = decode_entry(&commit.entry).with_context(|| {
+            format!(
+                "Invalid entry {} in {}",
+                commit.entry_hash,
+                lock_path.display()
+            )
+        })?;
+
+        match logs
+            .iter_mut()
+            .find(|log| &log.public_key == entry.public_key() && &log.log_id == entry.log_id())
+        {
+            Some(log) => log.commits.push((entry, commit)),
+            None => logs.push(Log {
+                public_key: entry.public_key().clone(),
+                log_id: *entry.log_id(),
+                commits: vec![(entry, commit)],
+            }),
+        }
+    }
+
+    Ok(logs)
+}
+
+/// Asks the node for the arguments of the next entry in the log of the given document.
+///
+/// Returns `None` if the node does not know about this document yet.
+async fn next_args(
+    client: &Client,
+    public_key: &PublicKey,
+    view_id: &Hash,
+) -> Result<Option<NextArguments>> {
+    let query = format!(
+        r#"
+        {{
+            nextArgs(publicKey: "{}", viewId: "{}") {{
+                logId
+                seqNum
+                skiplink
+                backlink
+            }}
+        }}
+        "#,
+        public_key, view_id,
+    );
+
+    let err = match client.query::<NextArgsResponse>(&query).await {
+        // Some nodes answer with `null` for documents they don't know
+        Ok(response) => return Ok(response.next_args),
+        Err(err) => err,
+    };
+
+    // Others answer with an error about the missing document. Only errors the node reported
+    // itself count, failed requests must never be mistaken for an unknown document
+    match err.downcast_ref::<GraphQLErrors>() {
+        Some(GraphQLErrors(messages))
+            if messages.iter().any(|message| message.contains("not found")) =>
+        {
+            Ok(None)
+        }
+        _ => Err(err.context(format!("Could not get next arguments for {view_id}"))),
+    }
+}
+
+/// Queries the node once per author log and returns how many commits of each log it already
+/// knows about.
+///
+/// The queries for all logs are sent concurrently.
+async fn known_commits(
+    client: &Client,
+    lock_path: &Path,
+    logs: &[Log],
+    lock_entries: &LockEntries,
+) -> Result<Vec<usize>> {
+    let queries = logs.iter().map(|log| async move {
+        // The first entry in every log is the one which created the document
+        let document_id = &log.commits[0].1.entry_hash;
+
+        match next_args(client, &log.public_key, document_id).await? {
+            Some(args) => {
+                check_divergence(lock_path, lock_entries, &log.public_key, &log.log_id, &args)?;
+
+                let known = log
+                    .commits
+                    .iter()
+                    .filter(|(entry, _)| entry.seq_num() < &args.seq_num)
+                    .count();
+
+                Ok(known)
+            }
+            None => Ok(0),
+        }
+    });
+
+    try_join_all(queries).await
+}
+
+/// Returns the hashes of all entries the given commit depends on, apart from its backlink.
+///
+/// Schema definitions depend on their field definitions, relation fields on the schema they
+/// point at.
+fn referenced_hashes(commit: &Commit) -> Result<Vec<Hash>> {
+    let operation = decode_operation(&commit.operation)?;
+    let fields = match operation.fields() {
+        Some(fields) => fields,
+        None => return Ok(Vec::new()),
+    };
+
+    let mut hashes = Vec::new();
+
+    if let Some(PlainValue::PinnedRelationList(field_view_ids)) = fields.get("fields") {
+        for field_hash in field_view_ids.iter().flatten() {
+            hashes.push(field_hash.parse()?);
+        }
+    }
+
+    if let Some(PlainValue::StringOrRelation(field_type)) = fields.get("type") {
+        if let Some(SchemaId::Application(_, view_id)) = related_schema_id(field_type) {
+            hashes.extend(
+                view_id
+                    .iter()
+                    .map(|operation_id| operation_id.as_hash().clone()),
+            );
+        }
+    }
+
+    Ok(hashes)
+}
+
+/// Sorts the commits into batches which can be published concurrently.
+///
+/// Every commit ends up in a later batch than all commits it depends on.
+fn batch_commits(commits: Vec<(Entry, Commit)>) -> Result<Vec<Vec<Commit>>> {
+    let mut levels: HashMap<Hash, usize> = HashMap::new();
+    let mut batches: Vec<Vec<Commit>> = Vec::new();
+
+    for (entry, commit) in commits {
+        let mut dependencies = referenced_hashes(&commit)?;
+        dependencies.extend(entry.backlink().cloned());
+
+        let level = dependencies
+            .iter()
+            .filter_map(|hash| levels.get(hash))
+            .map(|level| level + 1)
+            .max()
+            .unwrap_or(0);
+
+        levels.insert(commit.entry_hash.clone(), level);
+
+        if batches.len() <= level {
+            batches.resize_with(level + 1, Vec::new);
+        }
+
+        batches[level].push(commit);
+    }
+
+    Ok(batches)
+}
+
+async fn publish_commit(client: &Client, commit: &Commit) -> Result<()> {
+    let query = format!(
+        r#"
+        mutation Publish {{
+            publish(entry: "{}", operation: "{}") {{
+                logId
+                seqNum
+                skiplink
+                backlink
+            }}
+        }}
+        "#,
+        commit.entry, commit.operation
+    );
+
+    client
+        .query::<PublishResponse>(&query)
+        .await
+        .with_context(|| {
+            format!(
+                "GraphQL mutation `publish` failed for {}",
+                commit.entry_hash
+            )
+        })?;
+
+    Ok(())
+}
+
+/// Reports which commits of every author log the node is missing, without publishing anything.
+fn describe_missing(commits: &[Commit], logs: &[Log], known: &[usize]) -> Result<String> {
+    let operations = describe_commits(commits)?;
+    let schema_names = schema_names(commits, &operations)?;
+    let descriptions: HashMap<&Hash, &PlannedOperation> = commits
+        .iter()
+        .map(|commit| &commit.entry_hash)
+        .zip(&operations)
+        .collect();
+
+    let mut report = String::new();
+    let mut missing_total = 0;
+
+    for (log, known) in logs.iter().zip(known) {
+        let missing = &log.commits[*known..];
+
+        if missing.is_empty() {
+            continue;
+        }
+
+        report += &format!(
+            "Author {} log {}: missing {} of {} commits\n",
+            log.public_key,
+            log.log_id.as_u64(),
+            missing.len(),
+            log.commits.len()
+        );
+
+        for (entry, commit) in missing {
+            report += &format!(
+                "  {:<24} {:<40} seq num {}\n",
+                schema_names
+                    .get(&commit.entry_hash)
+                    .map(String::as_str)
+                    .unwrap_or("?"),
+                descriptions[&commit.entry_hash].to_string(),
+                entry.seq_num().as_u64()
+            );
+        }
+
+        missing_total += missing.len();
+    }
+
+    report += &format!(
+        "Dry run. Would publish {} commits (ignore {}).",
+        missing_total,
+        commits.len() - missing_total
+    );
+
+    Ok(report)
+}
+
+/// Loads the node options of the config file, if there is one.
+fn load_node_config(context: &Context) -> Result<NodeConfig> {
+    let config_file = match read_config_file(&context.config_path)? {
+        Some(config_file) => config_file,
+        None => return Ok(NodeConfig::default()),
+    };
+
+    let base = context
+        .config_path
+        .parent()
+        .map(|path| path.to_path_buf())
+        .unwrap_or_default();
+
+    Ok(config_file.node.relative_to(&base))
+}
+
+pub async fn publish(
+    context: Context,
+    node_config: NodeConfig,
+    schemas: &[SchemaName],
+    is_dry_run: bool,
+) -> Result<()> {
+    let mut commits = read_lock_file(&context.lock_path)?.commits;
+
+    if !schemas.is_empty() {
+        let (selected, external) = select_commits(&context.lock_path, commits, schemas)?;
+
+        for schema_id in external {
+            eprintln!(
+                "Warning: The selected schemas relate to {schema_id}, which is not part of {}. \
+                Make sure it is published as well",
+                context.lock_path.display()
+            );
+        }
+
+        commits = selected;
+    }
 
     if commits.is_empty() {
         bail!("Nothing to commit");
     }
 
-    let mut skipped = 0;
     let total = commits.len();
+    let logs = group_by_log(&context.lock_path, commits.clone())?;
+
+    let mut lock_entries: LockEntries = HashMap::new();
+
+    for log in &logs {
+        for (entry, commit) in &log.commits {
+            lock_entries.insert(
+                (log.public_key.clone(), log.log_id, *entry.seq_num()),
+                commit.entry_hash.clone(),
+            );
+        }
+    }
+
+    let node_config = node_config.merge(load_node_config(&context)?);
+    let client = Client::new(&node_config)?;
+
+    let started = Instant::now();
+    let known = known_commits(&client, &context.lock_path, &logs, &lock_entries).await?;
+
+    if is_dry_run {
+        println!("{}", describe_missing(&commits, &logs, &known)?);
+        return Ok(());
+    }
+
+    let skipped: usize = known.iter().sum();
+
+    let mut missing: Vec<(Entry, Commit)> = logs
+        .into_iter()
+        .zip(known)
+        .flat_map(|(log, known)| log.commits.into_iter().skip(known))
+        .collect();
+
+    // Bring the missing commits back into the order of the lock file before batching them
+    let order: HashMap<&Hash, usize> = commits
+        .iter()
+        .enumerate()
+        .map(|(index, commit)| (&commit.entry_hash, index))
+        .collect();
+    missing.sort_by_key(|(_, commit)| order[&commit.entry_hash]);
 
     let bar = ProgressBar::new(total as u64);
+    bar.inc(skipped as u64);
 
-    let client = Client::new(endpoint);
+    for batch in batch_commits(missing)? {
+        stream::iter(batch.iter())
+            .map(|commit| {
+                let bar = &bar;
+                let client = &client;
 
-    for commit in commits {
-        let entry = decode_entry(&commit.entry).unwrap();
+                async move {
+                    publish_commit(client, commit).await?;
+                    bar.inc(1);
+                    Ok::<(), Error>(())
+                }
+            })
+            .buffer_unordered(MAX_CONCURRENT_REQUESTS)
+            .try_collect::<Vec<()>>()
+            .await?;
+    }
+
+    bar.finish();
 
-        let query = format!(
+    println!(
+        "Done. Published {} commits (ignored {}) in {:.2}s.",
+        total - skipped,
+        skipped,
+        started.elapsed().as_secs_f64(),
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::net::TcpListener;
+    use std::path::PathBuf;
+
+    use p2panda_rs::identity::KeyPair;
+    use p2panda_rs::schema::SchemaName;
+    use p2panda_rs::test_utils::memory_store::MemoryStore;
+
+    use super::{
+        batch_commits, describe_missing, group_by_log, known_commits, publish, select_commits,
+        LockEntries,
+    };
+    use crate::client::Client;
+    use crate::commands::serve::spawn_node;
+    use crate::commands::update::plan_commits;
+    use crate::context::Context;
+    use crate::files::{write_lock_file, Commit, NodeConfig, SchemaFormat};
+    use crate::operations::describe_commits;
+    use crate::store::{build_lock_file, get_schemas, import_commits, ExternalSchemas};
+
+    fn context() -> Context {
+        let path = PathBuf::from("schema.lock");
+        Context::new(
+            MemoryStore::default(),
+            &path,
+            &path,
+            &path,
+            SchemaFormat::Toml,
+        )
+    }
+
+    /// Signs the commits which bring the given commits to the state of the schema file.
+    async fn sign(schema: &str, current: &[Commit], key_pair: &KeyPair) -> Vec<Commit> {
+        let schema_file = SchemaFormat::Toml.deserialize(schema).unwrap();
+        let key_pair = KeyPair::from_private_key_str(&hex::encode(key_pair.private_key())).unwrap();
+
+        plan_commits(
+            context(),
+            &schema_file,
+            current,
+            &ExternalSchemas::new(),
+            Box::new(key_pair),
+        )
+        .await
+        .unwrap()
+    }
+
+    /// Commits creating a `post` schema, and commits updating it and adding a `comment` schema.
+    async fn commits() -> (Vec<Commit>, Vec<Commit>) {
+        let key_pair = KeyPair::new();
+
+        let created = sign(
             r#"
-            {{
-                nextArgs(publicKey: "{}", viewId: "{}") {{
-                    logId
-                    seqNum
-                    skiplink
-                    backlink
-                }}
-            }}
+            [post]
+            description = "Blog post"
+            fields = { title = { type = "str" } }
             "#,
-            entry.public_key(),
-            commit.entry_hash,
-        );
+            &[],
+            &key_pair,
+        )
+        .await;
 
-        let response = client.query_unwrap::<NextArgsResponse>(&query).await;
+        let updated = sign(
+            r#"
+            [post]
+            description = "Post of a blog"
+            fields = { title = { type = "str" } }
 
-        if let Ok(result) = response {
-            let args = result.next_args;
+            [comment]
+            description = "Comment on a post"
+            fields.text = { type = "str" }
+            fields.post = { type = "relation", schema = { name = "post" } }
+            "#,
+            &created,
+            &key_pair,
+        )
+        .await;
 
-            if entry.log_id() != &args.log_id {
-                bail!("Inconsistency detected");
-            }
+        (created, updated)
+    }
 
-            if entry.seq_num() < &args.seq_num {
-                bar.inc(1);
-                skipped += 1;
+    fn lock_entries(commits: &[Commit]) -> LockEntries {
+        let mut lock_entries = HashMap::new();
 
-                // Skip this one
-                continue;
+        for log in group_by_log(&PathBuf::from("schema.lock"), commits.to_vec()).unwrap() {
+            for (entry, commit) in log.commits {
+                lock_entries.insert(
+                    (log.public_key.clone(), log.log_id, *entry.seq_num()),
+                    commit.entry_hash,
+                );
             }
         }
 
-        let query = format!(
+        lock_entries
+    }
+
+    #[tokio::test]
+    async fn batches_commits_after_their_dependencies() {
+        let (created, updated) = commits().await;
+        let commits = [created, updated].concat();
+        let descriptions: HashMap<_, _> = commits
+            .iter()
+            .map(|commit| commit.entry_hash.clone())
+            .zip(describe_commits(&commits).unwrap())
+            .collect();
+
+        let entries = group_by_log(&PathBuf::from("schema.lock"), commits.clone())
+            .unwrap()
+            .into_iter()
+            .flat_map(|log| log.commits)
+            .collect();
+        let batches = batch_commits(entries).unwrap();
+
+        assert_eq!(batches.iter().map(Vec::len).sum::<usize>(), commits.len());
+
+        let batch_of = |description: &str| {
+            batches
+                .iter()
+                .position(|batch| {
+                    batch
+                        .iter()
+                        .any(|commit| descriptions[&commit.entry_hash].to_string() == description)
+                })
+                .unwrap()
+        };
+
+        assert!(batch_of("create field 'title'") < batch_of("create schema 'post'"));
+        assert!(batch_of("create schema 'post'") < batch_of("update schema 'post'"));
+        assert!(batch_of("create schema 'post'") < batch_of("create field 'post'"));
+        assert!(batch_of("create field 'post'") < batch_of("create schema 'comment'"));
+    }
+
+    #[tokio::test]
+    async fn selects_related_schemas() {
+        let lock_path = PathBuf::from("custom.lock");
+        let commits = sign(
             r#"
-            mutation Publish {{
-                publish(entry: "{}", operation: "{}") {{
-                    logId
-                    seqNum
-                    skiplink
-                    backlink
-                }}
-            }}
+            [post]
+            description = "Blog post"
+            fields = { title = { type = "str" } }
+
+            [comment]
+            description = "Comment on a post"
+            fields.text = { type = "str" }
+            fields.post = { type = "relation", schema = { name = "post" } }
+
+            [tag]
+            description = "Tag"
+            fields = { label = { type = "str" } }
             "#,
-            commit.entry, commit.operation
+            &[],
+            &KeyPair::new(),
+        )
+        .await;
+
+        let selected_names = |schemas: &[&str]| {
+            let schemas: Vec<SchemaName> = schemas
+                .iter()
+                .map(|name| SchemaName::new(name).unwrap())
+                .collect();
+            let (selected, external) =
+                select_commits(&lock_path, commits.clone(), &schemas).unwrap();
+            assert!(external.is_empty());
+
+            let mut names: Vec<String> = describe_commits(&selected)
+                .unwrap()
+                .into_iter()
+                .map(|operation| operation.to_string())
+                .collect();
+            names.sort();
+            names
+        };
+
+        assert_eq!(
+            selected_names(&["comment"]),
+            [
+                "create field 'post'",
+                "create field 'text'",
+                "create field 'title'",
+                "create schema 'comment'",
+                "create schema 'post'",
+            ]
+        );
+        assert_eq!(
+            selected_names(&["tag"]),
+            ["create field 'label'", "create schema 'tag'"]
         );
 
-        client
-            .query_unwrap::<PublishResponse>(&query)
+        let err =
+            select_commits(&lock_path, commits, &[SchemaName::new("user").unwrap()]).unwrap_err();
+        assert_eq!(err.to_string(), "Schema 'user' not found in custom.lock");
+    }
+
+    #[tokio::test]
+    async fn counts_known_commits() {
+        let (created, updated) = commits().await;
+        let commits = [created.clone(), updated].concat();
+        let logs = group_by_log(&PathBuf::from("schema.lock"), commits.clone()).unwrap();
+
+        // The node only knows the commits which created the post schema
+        let node = context();
+        import_commits(&node.store, &created).await.unwrap();
+        let endpoint = spawn_node(node);
+
+        let client = Client::new(&NodeConfig {
+            endpoint: Some(endpoint.clone()),
+            ..NodeConfig::default()
+        })
+        .unwrap();
+
+        let known = known_commits(
+            &client,
+            &PathBuf::from("schema.lock"),
+            &logs,
+            &lock_entries(&commits),
+        )
+        .await
+        .unwrap();
+
+        let expected: Vec<usize> = logs
+            .iter()
+            .map(|log| {
+                log.commits
+                    .iter()
+                    .filter(|(_, commit)| {
+                        created
+                            .iter()
+                            .any(|known_commit| known_commit.entry_hash == commit.entry_hash)
+                    })
+                    .count()
+            })
+            .collect();
+        assert_eq!(known, expected);
+
+        // Nodes which can't be reached are not mistaken for empty ones
+        let address = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let client = Client::new(&NodeConfig {
+            endpoint: Some(format!("http://{address}/graphql")),
+            ..NodeConfig::default()
+        })
+        .unwrap();
+
+        assert!(known_commits(
+            &client,
+            &PathBuf::from("schema.lock"),
+            &logs,
+            &lock_entries(&commits)
+        )
+        .await
+        .is_err());
+
+        // Neither are wrong endpoints answering with "404 Not Found"
+        let client = Client::new(&NodeConfig {
+            endpoint: Some(endpoint.replace("/graphql", "/missing")),
+            ..NodeConfig::default()
+        })
+        .unwrap();
+
+        assert!(known_commits(
+            &client,
+            &PathBuf::from("schema.lock"),
+            &logs,
+            &lock_entries(&commits)
+        )
+        .await
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn describes_missing_commits() {
+        let (created, updated) = commits().await;
+        let commits = [created.clone(), updated.clone()].concat();
+        let logs = group_by_log(&PathBuf::from("schema.lock"), commits.clone()).unwrap();
+
+        // Pretend the node only knows the commits which created the post schema
+        let known: Vec<usize> = logs
+            .iter()
+            .map(|log| {
+                log.commits
+                    .iter()
+                    .filter(|(_, commit)| {
+                        created
+                            .iter()
+                            .any(|known_commit| known_commit.entry_hash == commit.entry_hash)
+                    })
+                    .count()
+            })
+            .collect();
+
+        let report = describe_missing(&commits, &logs, &known).unwrap();
+        let lines: Vec<&str> = report.lines().collect();
+
+        let line_of = |description: &str| {
+            lines
+                .iter()
+                .find(|line| line.contains(description))
+                .map(|line| line.split_whitespace().next().unwrap())
+        };
+
+        assert_eq!(line_of("update schema 'post'"), Some("post"));
+        assert_eq!(line_of("create schema 'comment'"), Some("comment"));
+        assert_eq!(line_of("create field 'text'"), Some("comment"));
+        assert_eq!(line_of("create schema 'post'"), None);
+        assert_eq!(line_of("create field 'title'"), None);
+        assert!(report.ends_with(&format!(
+            "Dry run. Would publish {} commits (ignore {}).",
+            updated.len(),
+            created.len()
+        )));
+    }
+
+    #[tokio::test]
+    async fn publishes_to_node() {
+        let dir = tempfile::tempdir().unwrap();
+        let lock_path = dir.path().join("schema.lock");
+
+        let (created, updated) = commits().await;
+        let lock_file = build_lock_file([created.clone(), updated].concat())
             .await
-            .expect("GraphQL mutation `publish` failed");
+            .unwrap();
+        write_lock_file(&lock_path, &lock_file).unwrap();
 
-        bar.inc(1);
+        // The node already knows the commits which created the post schema
+        let node = context();
+        import_commits(&node.store, &created).await.unwrap();
+        let node_config = NodeConfig {
+            endpoint: Some(spawn_node(node.clone())),
+            ..NodeConfig::default()
+        };
+
+        let context = Context::new(
+            MemoryStore::default(),
+            &dir.path().join("schema.toml"),
+            &lock_path,
+            &dir.path().join("fishy.toml"),
+            SchemaFormat::Toml,
+        );
+
+        publish(context.clone(), node_config.clone(), &[], false)
+            .await
+            .unwrap();
+
+        let schemas = get_schemas(&node.store).await.unwrap();
+        let mut names: Vec<String> = schemas.keys().map(|name| name.to_string()).collect();
+        names.sort();
+        assert_eq!(names, ["comment", "post"]);
+
+        let (_, post_view, _) = schemas
+            .values()
+            .find(|(schema, _, _)| schema.id().name().to_string() == "post")
+            .unwrap();
+        assert_eq!(post_view.description(), "Post of a blog");
+
+        // Publishing again doesn't find anything new
+        publish(context, node_config, &[], false).await.unwrap();
     }
 
-    println!(
-        "Done. Published {} commits (ignored {}).",
-        total - skipped,
-        skipped,
-    );
+    #[tokio::test]
+    async fn detects_forks() {
+        let dir = tempfile::tempdir().unwrap();
+        let lock_path = dir.path().join("custom.lock");
 
-    Ok(())
+        let key_pair = KeyPair::new();
+        let created = sign(
+            r#"
+            [post]
+            description = "Blog post"
+            fields = { title = { type = "str" } }
+            "#,
+            &[],
+            &key_pair,
+        )
+        .await;
+
+        // The same author updated the post twice from the same state, only one reached the node
+        let updated = sign(
+            r#"
+            [post]
+            description = "Post of a blog"
+            fields = { title = { type = "str" } }
+            "#,
+            &created,
+            &key_pair,
+        )
+        .await;
+        let forked = sign(
+            r#"
+            [post]
+            description = "Forked post"
+            fields = { title = { type = "str" } }
+            "#,
+            &created,
+            &key_pair,
+        )
+        .await;
+
+        let lock_file = build_lock_file([created.clone(), updated].concat())
+            .await
+            .unwrap();
+        write_lock_file(&lock_path, &lock_file).unwrap();
+
+        let node = context();
+        import_commits(&node.store, &[created, forked].concat())
+            .await
+            .unwrap();
+        let node_config = NodeConfig {
+            endpoint: Some(spawn_node(node)),
+            ..NodeConfig::default()
+        };
+
+        let context = Context::new(
+            MemoryStore::default(),
+            &dir.path().join("schema.toml"),
+            &lock_path,
+            &dir.path().join("fishy.toml"),
+            SchemaFormat::Toml,
+        );
+
+        let err = publish(context, node_config, &[], true).await.unwrap_err();
+        let message = format!("{err:#}");
+        assert!(message.contains("Fork detected"), "{message}");
+        assert!(
+            message.contains(&format!("If {} is outdated", lock_path.display())),
+            "{message}"
+        );
+    }
 }
@@ -0,0 +1,179 @@
+use std::net::SocketAddr;
+
+use anyhow::{anyhow, Context as ErrorContext, Result};
+use async_graphql::{
+    Context as GraphQLContext, EmptySubscription, Error as GraphQLError, Object,
+    Result as GraphQLResult, Schema as GraphQLSchema, SimpleObject,
+};
+use async_graphql_axum::{GraphQLRequest, GraphQLResponse};
+use axum::extract::State;
+use axum::routing::post;
+use axum::{Router, Server};
+use p2panda_rs::api::{next_args, publish};
+use p2panda_rs::document::DocumentViewId;
+use p2panda_rs::entry::{EncodedEntry, LogId, SeqNum};
+use p2panda_rs::hash::Hash;
+use p2panda_rs::identity::PublicKey;
+use p2panda_rs::operation::decode::decode_operation;
+use p2panda_rs::operation::traits::Schematic;
+use p2panda_rs::operation::EncodedOperation;
+use p2panda_rs::schema::{Schema as PandaSchema, SchemaId};
+
+use crate::context::Context;
+use crate::files::read_lock_file;
+use crate::store::{get_schemas, import_commits};
+
+type NodeSchema = GraphQLSchema<QueryRoot, MutationRoot, EmptySubscription>;
+
+/// Arguments for the next entry, serialized like a p2panda node does.
+#[derive(SimpleObject)]
+struct NextArguments {
+    log_id: String,
+    seq_num: String,
+    skiplink: Option<String>,
+    backlink: Option<String>,
+}
+
+impl NextArguments {
+    fn new(backlink: Option<Hash>, skiplink: Option<Hash>, seq_num: SeqNum, log_id: LogId) -> Self {
+        Self {
+            log_id: log_id.as_u64().to_string(),
+            seq_num: seq_num.as_u64().to_string(),
+            skiplink: skiplink.map(|hash| hash.to_string()),
+            backlink: backlink.map(|hash| hash.to_string()),
+        }
+    }
+}
+
+fn to_graphql_error(err: impl std::fmt::Display) -> GraphQLError {
+    GraphQLError::new(err.to_string())
+}
+
+struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// Arguments required to sign and encode the next entry of an author.
+    async fn next_args(
+        &self,
+        ctx: &GraphQLContext<'_>,
+        public_key: String,
+        view_id: Option<String>,
+    ) -> GraphQLResult<NextArguments> {
+        let context = ctx.data::<Context>()?;
+
+        let public_key = PublicKey::new(&public_key).map_err(to_graphql_error)?;
+        let view_id: Option<DocumentViewId> = view_id
+            .map(|view_id| view_id.parse())
+            .transpose()
+            .map_err(to_graphql_error)?;
+
+        let (backlink, skiplink, seq_num, log_id) =
+            next_args(&context.store, &public_key, view_id.as_ref())
+                .await
+                .map_err(to_graphql_error)?;
+
+        Ok(NextArguments::new(backlink, skiplink, seq_num, log_id))
+    }
+}
+
+struct MutationRoot;
+
+#[Object]
+impl MutationRoot {
+    /// Publish an entry with its operation on this node.
+    async fn publish(
+        &self,
+        ctx: &GraphQLContext<'_>,
+        entry: String,
+        operation: String,
+    ) -> GraphQLResult<NextArguments> {
+        let context = ctx.data::<Context>()?;
+
+        let encoded_entry = EncodedEntry::from_bytes(&hex::decode(entry)?);
+        let encoded_operation = EncodedOperation::from_bytes(&hex::decode(operation)?);
+        let plain_operation = decode_operation(&encoded_operation).map_err(to_graphql_error)?;
+
+        let schema = match plain_operation.schema_id() {
+            SchemaId::Application(_, _) => get_schemas(&context.store)
+                .await
+                .map_err(to_graphql_error)?
+                .into_values()
+                .map(|(schema, _, _)| schema)
+                .find(|schema| schema.id() == plain_operation.schema_id())
+                .ok_or(GraphQLError::new(format!(
+                    "Schema {} not found on this node",
+                    plain_operation.schema_id()
+                )))?,
+            schema_id => PandaSchema::get_system(schema_id.to_owned())
+                .map_err(to_graphql_error)?
+                .to_owned(),
+        };
+
+        let (backlink, skiplink, seq_num, log_id) = publish(
+            &context.store,
+            &schema,
+            &encoded_entry,
+            &plain_operation,
+            &encoded_operation,
+        )
+        .await
+        .map_err(to_graphql_error)?;
+
+        Ok(NextArguments::new(backlink, skiplink, seq_num, log_id))
+    }
+}
+
+async fn graphql_handler(State(schema): State<NodeSchema>, req: GraphQLRequest) -> GraphQLResponse {
+    schema.execute(req.into_inner()).await.into()
+}
+
+/// GraphQL endpoint of a node on top of the in-memory store of the context.
+fn router(context: Context) -> Router {
+    let schema = GraphQLSchema::build(QueryRoot, MutationRoot, EmptySubscription)
+        .data(context)
+        .finish();
+
+    Router::new()
+        .route("/graphql", post(graphql_handler))
+        .with_state(schema)
+}
+
+/// Runs a node on a free local port in the background and returns its endpoint.
+#[cfg(test)]
+pub fn spawn_node(context: Context) -> String {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+
+    let server = Server::from_tcp(listener)
+        .unwrap()
+        .serve(router(context).into_make_service());
+    tokio::spawn(server);
+
+    format!("http://{address}/graphql")
+}
+
+/// Runs a GraphQL endpoint on top of the in-memory store, preloaded with the lock file.
+pub async fn serve(context: Context, address: &SocketAddr) -> Result<()> {
+    if context.lock_path.exists() {
+        let commits = read_lock_file(&context.lock_path)?.commits;
+
+        import_commits(&context.store, &commits)
+            .await
+            .with_context(|| format!("Could not load {}", context.lock_path.display()))?;
+        println!(
+            "Loaded {} commits from {}",
+            commits.len(),
+            context.lock_path.display()
+        );
+    }
+
+    println!("Listening on http://{address}/graphql");
+
+    Server::try_bind(address)
+        .map_err(|err| anyhow!("Could not bind to {address}: {err}"))?
+        .serve(router(context).into_make_service())
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,42 @@
+use std::io;
+use std::path::Path;
+
+use anyhow::{Context as ErrorContext, Result};
+
+use crate::key::{read_key, KeySource};
+use crate::signer::serve_signer;
+
+/// Runs the reference signer, either on stdin and stdout or on a unix socket.
+pub fn signer(key_source: &KeySource, socket_path: Option<&Path>) -> Result<()> {
+    let key_pair = read_key(key_source)?;
+
+    match socket_path {
+        Some(socket_path) => serve_socket(&key_pair, socket_path),
+        None => serve_signer(&key_pair, io::stdin(), io::stdout()),
+    }
+}
+
+#[cfg(unix)]
+fn serve_socket(key_pair: &p2panda_rs::identity::KeyPair, socket_path: &Path) -> Result<()> {
+    use std::os::unix::net::UnixListener;
+
+    let listener = UnixListener::bind(socket_path)
+        .with_context(|| format!("Could not listen on {}", socket_path.display()))?;
+
+    eprintln!("Signer listening on {}", socket_path.display());
+
+    for stream in listener.incoming() {
+        let stream = stream?;
+
+        if let Err(err) = serve_signer(key_pair, stream.try_clone()?, stream) {
+            eprintln!("Signer connection failed: {err}");
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn serve_socket(_key_pair: &p2panda_rs::identity::KeyPair, _socket_path: &Path) -> Result<()> {
+    anyhow::bail!("Unix socket signers are only supported on unix systems")
+}
@@ -0,0 +1,53 @@
+use anyhow::Result;
+use p2panda_rs::identity::KeyPair;
+use p2panda_rs::test_utils::memory_store::MemoryStore;
+
+use crate::commands::update::plan_commits;
+use crate::context::Context;
+use crate::files::{read_lock_file, read_schema_file};
+use crate::operations::{describe_commits, print_inherited_fields, print_operations};
+use crate::store::ExternalSchemas;
+
+/// Prints the operations `update` would sign to bring the lock file in line with the schema file.
+///
+/// Returns true if the lock file is up to date.
+pub async fn status(context: Context, external: &ExternalSchemas) -> Result<bool> {
+    let schema_file = read_schema_file(&context.schema_path, context.schema_format)?;
+
+    let commits = read_lock_file(&context.lock_path)?.commits;
+
+    let context = Context::new(
+        MemoryStore::default(),
+        &context.schema_path,
+        &context.lock_path,
+        &context.config_path,
+        context.schema_format,
+    );
+
+    // Plan the commits with a throwaway key, only the operations are of interest
+    let simulated = plan_commits(
+        context.clone(),
+        &schema_file,
+        &commits,
+        external,
+        Box::new(KeyPair::new()),
+    )
+    .await?;
+
+    if simulated.is_empty() {
+        println!("{} is up to date", context.lock_path.display());
+        return Ok(true);
+    }
+
+    println!(
+        "{} pending operations for {}:",
+        simulated.len(),
+        context.lock_path.display()
+    );
+
+    let operations = describe_commits(&simulated)?;
+    print_operations(&operations);
+    print_inherited_fields(&context, &operations)?;
+
+    Ok(false)
+}
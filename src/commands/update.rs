@@ -1,20 +1,17 @@
 use std::collections::HashMap;
-use std::fs::{self, File};
-use std::io::Write;
-use std::path::PathBuf;
+use std::path::Path;
 
 use anyhow::{anyhow, bail, Context as ErrorContext, Result};
 use async_trait::async_trait;
-use p2panda_rs::api::publish;
-use p2panda_rs::document::traits::AsDocument;
+use p2panda_rs::api::{next_args, publish};
 use p2panda_rs::document::DocumentViewId;
 use p2panda_rs::entry::traits::AsEncodedEntry;
 use p2panda_rs::graph::Graph;
 use p2panda_rs::hash::Hash;
-use p2panda_rs::identity::KeyPair;
+use p2panda_rs::identity::PublicKey;
 use p2panda_rs::operation::decode::decode_operation;
 use p2panda_rs::operation::encode::encode_operation;
-use p2panda_rs::operation::traits::Schematic;
+use p2panda_rs::operation::traits::{Actionable, Schematic};
 use p2panda_rs::operation::{
     Operation, OperationAction, OperationBuilder, OperationValue, PinnedRelationList,
 };
@@ -23,23 +20,28 @@ use p2panda_rs::schema::{
     FieldName, FieldType as PandaFieldType, Schema as PandaSchema, SchemaDescription, SchemaId,
     SchemaName,
 };
-use p2panda_rs::storage_provider::traits::DocumentStore;
-use p2panda_rs::test_utils::memory_store::helpers::send_to_store;
 
+use crate::check::ensure_valid_schema_file;
 use crate::context::Context;
-use crate::files::{Commit, FieldType, LockFile, RelationType, SchemaFile};
+use crate::files::{
+    read_lock_file, read_schema_file, write_lock_file, Commit, FieldType, RelationId,
+    RelationSchema, RelationSource, RelationType, SchemaField, SchemaFile,
+};
 use crate::schema::Schema;
-
-fn write_file(path: &str, content: &str) -> Result<()> {
-    let mut file = File::create(&path)?;
-    file.write_all(content.as_bytes())?;
-    Ok(())
-}
+use crate::signer::{sign_entry, Signer};
+use crate::store::{
+    build_lock_file, get_schemas, import_commits, lock_file_schemas, verify_lock_file,
+    BuiltSchemas, ExternalSchemas,
+};
 
 struct Executor {
     context: Context,
-    key_pair: KeyPair,
+    signer: Box<dyn Signer>,
+    public_key: PublicKey,
     commits: Vec<Commit>,
+
+    /// View ids of the schemas executed so far, so relations don't create them twice.
+    view_ids: HashMap<SchemaName, DocumentViewId>,
 }
 
 impl Executor {
@@ -48,11 +50,33 @@ impl Executor {
 
         let encoded_operation = encode_operation(&operation)?;
 
-        let (encoded_entry, _) =
-            send_to_store(&self.context.store, &operation, &schema, &self.key_pair)
+        let (backlink, skiplink, seq_num, log_id) =
+            next_args(&self.context.store, &self.public_key, operation.previous())
                 .await
                 .map_err(|err| anyhow!("{err}"))?;
 
+        let encoded_entry = sign_entry(
+            self.signer.as_mut(),
+            &self.public_key,
+            &log_id,
+            &seq_num,
+            skiplink.as_ref(),
+            backlink.as_ref(),
+            &encoded_operation,
+        )?;
+
+        let plain_operation = decode_operation(&encoded_operation)?;
+
+        publish(
+            &self.context.store,
+            schema,
+            &encoded_entry,
+            &plain_operation,
+            &encoded_operation,
+        )
+        .await
+        .map_err(|err| anyhow!("{err}"))?;
+
         let entry_hash = encoded_entry.hash();
 
         self.commits
@@ -71,6 +95,16 @@ trait Executable {
 enum FieldTypePlan {
     Field(FieldType),
     Relation(RelationType, SchemaPlan),
+    External(RelationType, SchemaId),
+}
+
+fn relation_field_type(relation: &RelationType, schema_id: SchemaId) -> PandaFieldType {
+    match relation {
+        RelationType::Relation => PandaFieldType::Relation(schema_id),
+        RelationType::RelationList => PandaFieldType::RelationList(schema_id),
+        RelationType::PinnedRelation => PandaFieldType::PinnedRelation(schema_id),
+        RelationType::PinnedRelationList => PandaFieldType::PinnedRelationList(schema_id),
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -91,15 +125,10 @@ impl Executable for FieldPlan {
             FieldTypePlan::Relation(relation, schema_plan) => {
                 let view_id = schema_plan.execute(executor).await?;
                 let schema_id = SchemaId::new_application(&schema_plan.name, &view_id);
-
-                match relation {
-                    RelationType::Relation => PandaFieldType::Relation(schema_id),
-                    RelationType::RelationList => PandaFieldType::RelationList(schema_id),
-                    RelationType::PinnedRelation => PandaFieldType::PinnedRelation(schema_id),
-                    RelationType::PinnedRelationList => {
-                        PandaFieldType::PinnedRelationList(schema_id)
-                    }
-                }
+                relation_field_type(relation, schema_id)
+            }
+            FieldTypePlan::External(relation, schema_id) => {
+                relation_field_type(relation, schema_id.clone())
             }
         };
 
@@ -154,6 +183,10 @@ struct SchemaPlan {
 #[async_trait]
 impl Executable for SchemaPlan {
     async fn execute(&self, executor: &mut Executor) -> Result<DocumentViewId> {
+        if let Some(view_id) = executor.view_ids.get(&self.name) {
+            return Ok(view_id.clone());
+        }
+
         let mut schema_fields: Vec<DocumentViewId> = Vec::new();
 
         for field in &self.fields {
@@ -203,23 +236,26 @@ impl Executable for SchemaPlan {
             }
         };
 
-        match operation {
-            Some(operation) => {
-                let entry_hash = executor
-                    .sign(&operation, SchemaId::SchemaDefinition(1))
-                    .await?;
+        let view_id: DocumentViewId = match operation {
+            Some(operation) => executor
+                .sign(&operation, SchemaId::SchemaDefinition(1))
+                .await?
+                .into(),
+            None => self.current.as_ref().unwrap().view_id().clone(),
+        };
 
-                Ok(entry_hash.into())
-            }
-            None => Ok(self.current.as_ref().unwrap().view_id().clone().into()),
-        }
+        executor.view_ids.insert(self.name.clone(), view_id.clone());
+
+        Ok(view_id)
     }
 }
 
 async fn do_it(
-    current: HashMap<SchemaName, (PandaSchema, SchemaView, Vec<SchemaFieldView>)>,
+    current: BuiltSchemas,
     planned: Vec<Schema>,
+    external: &ExternalSchemas,
     context: Context,
+    mut signer: Box<dyn Signer>,
 ) -> Result<Vec<Commit>> {
     let mut graph = Graph::new();
 
@@ -234,14 +270,12 @@ async fn do_it(
                     field_type: _,
                     schema,
                 } => {
-                    match &schema.id {
-                        crate::files::RelationId::Name(linked_schema) => {
+                    // Relations to schemas outside of the schema file don't need to be ordered
+                    if let crate::files::RelationId::Name(linked_schema) = &schema.id {
+                        if planned.iter().any(|schema| schema.name() == linked_schema) {
                             graph.add_link(linked_schema, planned_schema.name());
                         }
-                        crate::files::RelationId::Id(_) => {
-                            todo!("Not supported yet")
-                        }
-                    };
+                    }
                 }
                 _ => (),
             }
@@ -289,17 +323,39 @@ async fn do_it(
 
         for (planned_field_name, planned_field_type) in planned_schema.fields().iter() {
             let field_type = match planned_field_type {
-                crate::files::SchemaField::Field { field_type } => {
+                crate::files::SchemaField::Field { field_type, .. } => {
                     FieldTypePlan::Field(field_type.clone())
                 }
                 crate::files::SchemaField::Relation { field_type, schema } => match &schema.id {
-                    crate::files::RelationId::Name(related_schema_name) => {
+                    crate::files::RelationId::Name(related_schema_name)
+                        if planned
+                            .iter()
+                            .any(|schema| schema.name() == related_schema_name) =>
+                    {
                         let planned_schema =
                             get_planned_schema(&planned_schemas, related_schema_name);
                         FieldTypePlan::Relation(field_type.clone(), planned_schema)
                     }
-                    crate::files::RelationId::Id(_) => todo!(),
+                    crate::files::RelationId::Name(related_schema_name) => {
+                        let schema_id = external.get(related_schema_name).with_context(|| {
+                            format!(
+                                "Field '{planned_field_name}' of schema {} relates to unknown \
+                                schema {related_schema_name}",
+                                planned_schema.name()
+                            )
+                        })?;
+                        FieldTypePlan::External(field_type.clone(), schema_id.clone())
+                    }
+                    crate::files::RelationId::Id(schema_id) => {
+                        FieldTypePlan::External(field_type.clone(), schema_id.clone())
+                    }
                 },
+                // Schema files from other sources than `read_schema_file` might not be expanded
+                crate::files::SchemaField::Object { .. } => bail!(
+                    "Field '{planned_field_name}' of schema {} has nested fields which were not \
+                    expanded into a generated schema",
+                    planned_schema.name()
+                ),
             };
 
             let current_schema_field = get_current_field(&schema_current, planned_field_name);
@@ -327,40 +383,105 @@ async fn do_it(
 
     let mut executor = Executor {
         context,
-        key_pair: KeyPair::new(),
+        public_key: signer.public_key()?,
+        signer,
         commits: Vec::new(),
+        view_ids: HashMap::new(),
     };
 
-    let first_schema = planned_schemas.pop().unwrap();
-    first_schema.execute(&mut executor).await?;
+    for schema_plan in &planned_schemas {
+        schema_plan.execute(&mut executor).await?;
+    }
 
     return Ok(executor.commits);
 }
 
-pub async fn update(context: Context, private_key_path: &PathBuf) -> Result<()> {
-    let schema_file_str = fs::read_to_string(&context.schema_path)?;
-    let schema_file: SchemaFile =
-        toml::from_str(&schema_file_str).with_context(|| "Invalid schema.toml format")?;
+/// Looks up the ids of related schemas which are defined in the lock file of another folder,
+/// given as `path` of the relation.
+async fn resolve_paths(
+    context: &Context,
+    schema_file: &SchemaFile,
+    external: &mut ExternalSchemas,
+) -> Result<()> {
+    let base = context.schema_path.parent().unwrap_or(Path::new(""));
+    let lock_file_name = context
+        .lock_path
+        .file_name()
+        .unwrap_or("schema.lock".as_ref());
+
+    for (_, schema_item) in schema_file.iter() {
+        for (_, field) in schema_item.fields.iter() {
+            let (related_schema_name, path) = match field {
+                SchemaField::Relation {
+                    schema:
+                        RelationSchema {
+                            id: RelationId::Name(name),
+                            external: Some(RelationSource::Path(path)),
+                        },
+                    ..
+                } => (name, path),
+                _ => continue,
+            };
 
-    let lock_file_str = fs::read_to_string(&context.lock_path);
-    let mut lock_file = match lock_file_str {
-        Ok(file_str) => {
-            let lock_file: LockFile = toml::from_str(&file_str)?;
-            lock_file
+            let lock_path = base.join(path).join(lock_file_name);
+            let schema_id = lock_file_schemas(&read_lock_file(&lock_path)?)
+                .await?
+                .remove(related_schema_name)
+                .with_context(|| {
+                    format!(
+                        "Schema {related_schema_name} not found in {}",
+                        lock_path.display()
+                    )
+                })?;
+
+            external.insert(related_schema_name.clone(), schema_id);
         }
-        Err(_) => LockFile::new(vec![]),
-    };
+    }
 
-    let private_key_file_str = fs::read_to_string(&private_key_path)?;
-    let key_pair = KeyPair::from_private_key_str(&private_key_file_str)?;
+    Ok(())
+}
 
-    println!("{}", key_pair.public_key());
+/// Returns the ids of all related schemas which are not defined in the schema file, from the
+/// given external schemas or the lock file in the `path` of the relation.
+pub async fn resolve_external(
+    context: &Context,
+    schema_file: &SchemaFile,
+    external: &ExternalSchemas,
+) -> Result<ExternalSchemas> {
+    let mut external = external.clone();
+    resolve_paths(context, schema_file, &mut external).await?;
+
+    Ok(external)
+}
 
+/// Signs the commits which bring the schemas of the current commits to the state of the schema
+/// file.
+///
+/// Relations to schemas which are not part of the schema file are resolved with the given
+/// external schemas or the lock file in the `path` of the relation.
+pub async fn plan_commits(
+    context: Context,
+    schema_file: &SchemaFile,
+    current_commits: &[Commit],
+    external: &ExternalSchemas,
+    signer: Box<dyn Signer>,
+) -> Result<Vec<Commit>> {
+    let external = resolve_external(&context, schema_file, external).await?;
+    plan_resolved_commits(context, schema_file, current_commits, &external, signer).await
+}
+
+/// Signs the commits like `plan_commits`, with all related schemas outside of the schema file
+/// already resolved, so no other lock files are read.
+pub async fn plan_resolved_commits(
+    context: Context,
+    schema_file: &SchemaFile,
+    current_commits: &[Commit],
+    external: &ExternalSchemas,
+    signer: Box<dyn Signer>,
+) -> Result<Vec<Commit>> {
     // GET THE PLANNED SCHEMAS
 
     let mut planned_schemas: Vec<Schema> = Vec::new();
-    let mut built_schemas: HashMap<SchemaName, (PandaSchema, SchemaView, Vec<SchemaFieldView>)> =
-        HashMap::new();
 
     for (schema_name, schema_item) in schema_file.iter() {
         let schema = Schema::new(schema_name, &schema_item.description, &schema_item.fields);
@@ -374,101 +495,116 @@ pub async fn update(context: Context, private_key_path: &PathBuf) -> Result<()>
 
     // GET THE CURRENT SCHEMAS
 
-    if let Some(commits) = &lock_file.commits {
-        for commit in commits {
-            let plain_operation = decode_operation(&commit.operation)?;
-
-            let schema = match plain_operation.schema_id() {
-            SchemaId::SchemaDefinition(version) => PandaSchema::get_system(
-                SchemaId::SchemaDefinition(*version),
-            )
-            .with_context(|| {
-                "Incompatible system schema definition version {version} used in schema.lock"
-            })?,
-            SchemaId::SchemaFieldDefinition(version) => PandaSchema::get_system(
-                SchemaId::SchemaFieldDefinition(*version),
-            )
-            .with_context(|| {
-                "Incompatible system schema field definition version {version} used in schema.lock"
-            })?,
-            value => bail!("Invalid schema id '{value}' detected in schema.lock"),
-        };
-
-            publish(
-                &context.store,
-                schema,
-                &commit.entry,
-                &plain_operation,
-                &commit.operation,
-            )
-            .await?;
-        }
-
-        let definition_documents = context
-            .store
-            .get_documents_by_schema(&SchemaId::SchemaDefinition(1))
-            .await?;
+    import_commits(&context.store, current_commits).await?;
+    let built_schemas = get_schemas(&context.store).await?;
 
-        for definition_document in definition_documents {
-            let document_view = definition_document.view().unwrap();
-            let schema_view = SchemaView::try_from(document_view).unwrap();
-            let mut schema_field_views: Vec<SchemaFieldView> = Vec::new();
+    // DO IT
 
-            for field_view_id in schema_view.fields().iter() {
-                let field_document = context
-                    .store
-                    .get_document_by_view_id(field_view_id)
-                    .await
-                    .unwrap()
-                    .unwrap();
+    do_it(built_schemas, planned_schemas, external, context, signer).await
+}
 
-                let schema_field_view =
-                    SchemaFieldView::try_from(field_document.view().unwrap()).unwrap();
+pub async fn update(
+    context: Context,
+    mut signer: Box<dyn Signer>,
+    external: &ExternalSchemas,
+) -> Result<()> {
+    let external_names: Vec<SchemaName> = external.keys().cloned().collect();
+    ensure_valid_schema_file(&context.schema_path, context.schema_format, &external_names)?;
 
-                schema_field_views.push(schema_field_view);
-            }
+    let schema_file = read_schema_file(&context.schema_path, context.schema_format)?;
 
-            let schema =
-                PandaSchema::from_views(schema_view.clone(), schema_field_views.clone()).unwrap();
-
-            if built_schemas
-                .insert(
-                    schema.id().name(),
-                    (schema, schema_view, schema_field_views),
-                )
-                .is_some()
-            {
-                bail!("Duplicate schema name detected in schema.lock");
-            }
-        }
-    }
+    let lock_path = context.lock_path.clone();
+    let lock_file = read_lock_file(&lock_path)?;
+    verify_lock_file(&lock_path, &lock_file).await?;
+    let mut commits = lock_file.commits;
 
-    // DO IT
+    println!("{}", signer.public_key()?);
 
-    let mut new_commits = do_it(built_schemas, planned_schemas, context).await?;
+    let mut new_commits = plan_commits(context, &schema_file, &commits, external, signer).await?;
     println!("Writing {} new commits", new_commits.len());
 
     // TODO: ASK IF WE'RE OKAY W. THAT
 
-    let mut commits: Vec<Commit> = Vec::new();
-
-    if let Some(current_commits) = lock_file.commits.as_mut() {
-        commits.append(current_commits);
-    }
     commits.append(&mut new_commits);
 
     // WRITE TO .LOCK FILE
 
-    let lock_file = LockFile::new(commits);
-
-    let lock_file_str = format!(
-        "{}\n\n{}",
-        "# This file is automatically generated by fishy.\n# It is not intended for manual editing.",
-        toml::to_string_pretty(&lock_file)?
-    );
-
-    write_file("schema.lock", &lock_file_str)
-        .with_context(|| "Could not create schema.lock file")?;
+    write_lock_file(&lock_path, &build_lock_file(commits).await?)
+}
 
-    Ok(())
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use p2panda_rs::identity::KeyPair;
+    use p2panda_rs::test_utils::memory_store::MemoryStore;
+
+    use super::plan_commits;
+    use crate::context::Context;
+    use crate::files::{LockFile, LockHeader, SchemaFormat};
+    use crate::store::{build_lock_file, lock_file_schemas, verify_lock_file, ExternalSchemas};
+
+    #[tokio::test]
+    async fn verifies_lock_files() {
+        let path = PathBuf::from("schema.lock");
+        let schema_file = SchemaFormat::Toml
+            .deserialize(
+                r#"
+                [post]
+                description = "Blog post"
+                fields = { title = { type = "str" } }
+                "#,
+            )
+            .unwrap();
+
+        let key_pair = KeyPair::new();
+        let public_key = key_pair.public_key().to_string();
+        let context = Context::new(
+            MemoryStore::default(),
+            &path,
+            &path,
+            &path,
+            SchemaFormat::Toml,
+        );
+        let commits = plan_commits(
+            context,
+            &schema_file,
+            &[],
+            &ExternalSchemas::new(),
+            Box::new(key_pair),
+        )
+        .await
+        .unwrap();
+
+        let mut lock_file = build_lock_file(commits.clone()).await.unwrap();
+        assert_eq!(lock_file.header.authors, [public_key]);
+        let schema_names: Vec<String> = lock_file
+            .schemas
+            .keys()
+            .map(|name| name.to_string())
+            .collect();
+        assert_eq!(schema_names, ["post"]);
+        verify_lock_file(&path, &lock_file).await.unwrap();
+
+        // A stale schema index is detected
+        lock_file.schemas.clear();
+        assert!(verify_lock_file(&path, &lock_file).await.is_err());
+
+        // Lock files of version 1 don't have an index, their commits are replayed instead
+        let lock_file_v1 = LockFile {
+            version: 1,
+            header: LockHeader::default(),
+            schemas: Default::default(),
+            commits,
+        };
+        verify_lock_file(&path, &lock_file_v1).await.unwrap();
+        let schemas = lock_file_schemas(&lock_file_v1).await.unwrap();
+        assert_eq!(
+            schemas
+                .keys()
+                .map(|name| name.to_string())
+                .collect::<Vec<String>>(),
+            ["post"]
+        );
+    }
 }
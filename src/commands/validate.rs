@@ -0,0 +1,233 @@
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{Context as ErrorContext, Result};
+use p2panda_rs::schema::SchemaName;
+use regex::Regex;
+use toml::{Table, Value};
+
+use crate::check::{compile_patterns, FieldPatterns};
+use crate::context::Context;
+use crate::files::{
+    read_schema_file, FieldConstraints, FieldType, RelationType, SchemaField, SchemaFormat,
+    SchemaItem,
+};
+
+fn check_number(constraints: &FieldConstraints, number: f64) -> Result<(), String> {
+    if let Some(min) = constraints.min.filter(|min| number < *min) {
+        return Err(format!("{number} is smaller than {min}"));
+    }
+
+    if let Some(max) = constraints.max.filter(|max| number > *max) {
+        return Err(format!("{number} is larger than {max}"));
+    }
+
+    Ok(())
+}
+
+fn check_string(
+    constraints: &FieldConstraints,
+    pattern: Option<&Regex>,
+    string: &str,
+) -> Result<(), String> {
+    if let Some(values) = &constraints.values {
+        if !values.iter().any(|value| value == string) {
+            return Err(format!("'{string}' is not one of: {}", values.join(", ")));
+        }
+    }
+
+    if let Some(pattern) = pattern.filter(|pattern| !pattern.is_match(string)) {
+        return Err(format!("'{string}' doesn't match the pattern {pattern}"));
+    }
+
+    Ok(())
+}
+
+fn is_string_list(value: &Value) -> bool {
+    value
+        .as_array()
+        .map_or(false, |values| values.iter().all(Value::is_str))
+}
+
+/// Checks a value against the type and constraints of its field.
+fn check_value(field: &SchemaField, pattern: Option<&Regex>, value: &Value) -> Result<(), String> {
+    match field {
+        SchemaField::Field {
+            field_type,
+            constraints,
+        } => match (field_type, value) {
+            (FieldType::Boolean, Value::Boolean(_)) => Ok(()),
+            (FieldType::Integer, Value::Integer(int)) => check_number(constraints, *int as f64),
+            (FieldType::Float, Value::Integer(int)) => check_number(constraints, *int as f64),
+            (FieldType::Float, Value::Float(float)) => check_number(constraints, *float),
+            (FieldType::String, Value::String(string)) => {
+                check_string(constraints, pattern, string)
+            }
+            (FieldType::Boolean, _) => Err("expected a boolean".to_string()),
+            (FieldType::Integer, _) => Err("expected an integer".to_string()),
+            (FieldType::Float, _) => Err("expected a number".to_string()),
+            (FieldType::String, _) => Err("expected a string".to_string()),
+        },
+        SchemaField::Relation { field_type, .. } => {
+            let is_valid = match field_type {
+                RelationType::Relation => value.is_str(),
+                RelationType::RelationList | RelationType::PinnedRelation => is_string_list(value),
+                RelationType::PinnedRelationList => value
+                    .as_array()
+                    .map_or(false, |values| values.iter().all(is_string_list)),
+            };
+
+            match (is_valid, field_type) {
+                (true, _) => Ok(()),
+                (false, RelationType::Relation) => Err("expected a document id".to_string()),
+                (false, RelationType::RelationList) => {
+                    Err("expected a list of document ids".to_string())
+                }
+                (false, RelationType::PinnedRelation) => {
+                    Err("expected a document view id, given as list of operation ids".to_string())
+                }
+                (false, RelationType::PinnedRelationList) => {
+                    Err("expected a list of document view ids".to_string())
+                }
+            }
+        }
+        SchemaField::Object { .. } => {
+            Err("nested fields have to be expanded into a generated schema first".to_string())
+        }
+    }
+}
+
+/// Returns all problems of a document of the schema.
+fn validate_document(
+    schema_item: &SchemaItem,
+    patterns: &FieldPatterns,
+    document: &Table,
+) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    for (field_name, field) in schema_item.fields.iter() {
+        match document.get(field_name) {
+            Some(value) => {
+                if let Err(problem) = check_value(field, patterns.get(field_name), value) {
+                    problems.push(format!("Field '{field_name}': {problem}"));
+                }
+            }
+            None => {
+                let is_optional = matches!(
+                    field,
+                    SchemaField::Field {
+                        constraints: FieldConstraints { optional: true, .. },
+                        ..
+                    }
+                );
+
+                if !is_optional {
+                    problems.push(format!("Missing field '{field_name}'"));
+                }
+            }
+        }
+    }
+
+    for field_name in document.keys() {
+        if !schema_item
+            .fields
+            .iter()
+            .any(|(name, _)| name == field_name)
+        {
+            problems.push(format!("Unknown field '{field_name}'"));
+        }
+    }
+
+    problems
+}
+
+/// Checks documents against the types and local constraints of the fields of a schema.
+///
+/// Every file contains the fields of one document, in any of the schema file formats. Returns
+/// true if all documents are valid.
+pub fn validate(context: Context, schema_name: &SchemaName, paths: &[PathBuf]) -> Result<bool> {
+    let schema_file = read_schema_file(&context.schema_path, context.schema_format)?;
+    let (_, schema_item) = schema_file
+        .iter()
+        .find(|(name, _)| *name == schema_name)
+        .with_context(|| format!("Schema {schema_name} is not defined"))?;
+    let patterns = compile_patterns(schema_name, schema_item)?;
+
+    let mut is_valid = true;
+
+    for path in paths {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Could not read {}", path.display()))?;
+        let document = SchemaFormat::from_path(path)
+            .parse(&content)
+            .with_context(|| format!("Invalid {} format", path.display()))?;
+
+        let problems = validate_document(schema_item, &patterns, &document);
+        if problems.is_empty() {
+            println!("{} is a valid {schema_name} document", path.display());
+            continue;
+        }
+
+        is_valid = false;
+
+        println!("{} is not a valid {schema_name} document:", path.display());
+        for problem in problems {
+            println!("  {problem}");
+        }
+    }
+
+    Ok(is_valid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::validate_document;
+    use crate::check::compile_patterns;
+    use crate::files::SchemaFormat;
+
+    #[test]
+    fn validates_documents() {
+        let schema_file = SchemaFormat::Toml
+            .deserialize(
+                r#"
+                [post]
+                description = ""
+
+                [post.fields]
+                title = { type = "str", pattern = "^[A-Z]" }
+                status = { type = "str", values = ["draft", "published"] }
+                rating = { type = "int", min = 1, max = 5, optional = true }
+                author = { type = "relation", schema = { name = "author" } }
+                "#,
+            )
+            .unwrap();
+        let (post_name, post) = schema_file.iter().next().unwrap();
+        let patterns = compile_patterns(post_name, post).unwrap();
+
+        let valid = SchemaFormat::Json
+            .parse(r#"{ "title": "Hello", "status": "draft", "author": "0020c65567ae3" }"#)
+            .unwrap();
+        assert!(validate_document(post, &patterns, &valid).is_empty());
+
+        let invalid = SchemaFormat::Toml
+            .parse(
+                r#"
+                title = "hello"
+                status = "deleted"
+                rating = 6
+                tags = ["news"]
+                "#,
+            )
+            .unwrap();
+        assert_eq!(
+            validate_document(post, &patterns, &invalid),
+            [
+                "Missing field 'author'",
+                "Field 'rating': 6 is larger than 5",
+                "Field 'status': 'deleted' is not one of: draft, published",
+                "Field 'title': 'hello' doesn't match the pattern ^[A-Z]",
+                "Unknown field 'tags'",
+            ]
+        );
+    }
+}
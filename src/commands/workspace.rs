@@ -0,0 +1,291 @@
+use anyhow::{bail, Result};
+use p2panda_rs::schema::{SchemaId, SchemaName};
+
+use crate::check::check_schema_file;
+use crate::commands::fmt::fmt;
+use crate::commands::publish::{publish, select_commits};
+use crate::commands::status::status;
+use crate::commands::update::update;
+use crate::context::Context;
+use crate::files::{read_lock_file, NodeConfig, SchemaFormat};
+use crate::signer::Signer;
+use crate::store::{lock_file_schemas, ExternalSchemas};
+use crate::workspace::{sort_members, Member};
+
+/// Ids of all schemas in the lock file of a member, to resolve relations of the following ones.
+async fn member_schemas(member: &Member) -> Result<ExternalSchemas> {
+    lock_file_schemas(&read_lock_file(&member.lock_path)?).await
+}
+
+/// Updates the lock files of all members, in dependency order.
+pub async fn workspace_update(
+    context: Context,
+    members: &[Member],
+    mut signer_for: impl FnMut(&Member) -> Result<Box<dyn Signer>>,
+) -> Result<()> {
+    let mut external = ExternalSchemas::new();
+
+    for member in sort_members(members)? {
+        println!("Updating {}", member.path.display());
+
+        update(member.context(&context), signer_for(member)?, &external).await?;
+
+        external.extend(member_schemas(member).await?);
+    }
+
+    Ok(())
+}
+
+/// Names of the schemas to publish from every member, including the schemas of other members
+/// the selected schemas relate to.
+async fn select_member_schemas(
+    members: &[&Member],
+    schemas: &[SchemaName],
+) -> Result<Vec<Vec<SchemaName>>> {
+    let mut required: Vec<SchemaId> = Vec::new();
+    let mut found: Vec<&SchemaName> = Vec::new();
+    let mut selections = Vec::new();
+
+    // Members only relate to earlier ones, walk backwards to collect what they depend on
+    for member in members.iter().rev() {
+        let lock_file = read_lock_file(&member.lock_path)?;
+        let member_schemas = lock_file_schemas(&lock_file).await?;
+
+        let selected: Vec<SchemaName> = member_schemas
+            .iter()
+            .filter(|(schema_name, schema_id)| {
+                schemas.contains(schema_name) || required.contains(schema_id)
+            })
+            .map(|(schema_name, _)| schema_name.clone())
+            .collect();
+
+        if !selected.is_empty() {
+            let (_, external) = select_commits(&member.lock_path, lock_file.commits, &selected)?;
+            required.extend(external);
+        }
+
+        found.extend(
+            schemas
+                .iter()
+                .filter(|schema_name| member_schemas.contains_key(*schema_name)),
+        );
+        selections.push(selected);
+    }
+
+    if let Some(schema_name) = schemas.iter().find(|name| !found.contains(name)) {
+        bail!("Schema '{schema_name}' not found in the lock files of the workspace");
+    }
+
+    selections.reverse();
+    Ok(selections)
+}
+
+/// Publishes the lock files of all members, in dependency order.
+pub async fn workspace_publish(
+    context: Context,
+    members: &[Member],
+    node_config: NodeConfig,
+    schemas: &[SchemaName],
+    is_dry_run: bool,
+) -> Result<()> {
+    let members = sort_members(members)?;
+    let selections = if schemas.is_empty() {
+        None
+    } else {
+        Some(select_member_schemas(&members, schemas).await?)
+    };
+
+    for (index, member) in members.into_iter().enumerate() {
+        if member_schemas(member).await?.is_empty() {
+            println!("Skipping {}, nothing to publish", member.path.display());
+            continue;
+        }
+
+        let selected = match &selections {
+            Some(selections) if selections[index].is_empty() => continue,
+            Some(selections) => selections[index].clone(),
+            None => Vec::new(),
+        };
+
+        println!("Publishing {}", member.path.display());
+
+        publish(
+            member.context(&context),
+            node_config.clone(),
+            &selected,
+            is_dry_run,
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Reports the pending operations of all members.
+///
+/// Returns true if all lock files are up to date.
+pub async fn workspace_status(context: Context, members: &[Member]) -> Result<bool> {
+    let mut external = ExternalSchemas::new();
+    let mut is_up_to_date = true;
+
+    for member in sort_members(members)? {
+        println!("{}:", member.path.display());
+
+        is_up_to_date &= status(member.context(&context), &external).await?;
+        external.extend(member_schemas(member).await?);
+    }
+
+    Ok(is_up_to_date)
+}
+
+/// Checks the schema files of all members, reporting the problems of every member.
+///
+/// Relations may point to the schemas of all other members.
+pub fn workspace_check(members: &[Member]) -> Result<()> {
+    // Members which can't be read define nothing, their problems are reported below
+    let defined: Vec<Vec<SchemaName>> = members
+        .iter()
+        .map(|member| match member.read_schema_file() {
+            Ok(schema_file) => schema_file.iter().map(|(name, _)| name.clone()).collect(),
+            Err(_) => Vec::new(),
+        })
+        .collect();
+
+    let mut failed = 0;
+
+    for (index, member) in members.iter().enumerate() {
+        let external: Vec<SchemaName> = defined
+            .iter()
+            .enumerate()
+            .filter(|(other, _)| *other != index)
+            .flat_map(|(_, names)| names.iter().cloned())
+            .collect();
+
+        let diagnostics = check_schema_file(&member.schema_path, member.schema_format, &external);
+        for diagnostic in &diagnostics {
+            eprintln!("{diagnostic}\n");
+        }
+
+        if diagnostics.is_empty() {
+            println!("No problems found in {}", member.schema_path.display());
+        } else {
+            failed += 1;
+        }
+    }
+
+    if failed > 0 {
+        bail!("Found problems in {failed} of {} members", members.len());
+    }
+
+    Ok(())
+}
+
+/// Formats the TOML schema files of all members.
+///
+/// Returns true if all files were formatted already.
+pub fn workspace_fmt(context: Context, members: &[Member], check: bool) -> Result<bool> {
+    let mut is_formatted = true;
+
+    for member in members {
+        let member_context = member.context(&context);
+
+        if member_context.schema_format != SchemaFormat::Toml {
+            println!(
+                "Skipping {}, only TOML files are formatted",
+                member.schema_path.display()
+            );
+            continue;
+        }
+
+        is_formatted &= fmt(member_context, check)?;
+    }
+
+    Ok(is_formatted)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use p2panda_rs::identity::KeyPair;
+    use p2panda_rs::test_utils::memory_store::MemoryStore;
+
+    use super::{workspace_check, workspace_status, workspace_update};
+    use crate::context::Context;
+    use crate::files::SchemaFormat;
+    use crate::workspace::{read_workspace, sort_members, Member};
+
+    #[tokio::test]
+    async fn works_through_members_in_dependency_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |path: &str, content: &str| {
+            let path = dir.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        };
+
+        write(
+            "fishy.toml",
+            "[workspace]\nmembers = [\"blog\", \"users\", \"broken\"]\n",
+        );
+        write(
+            "blog/schema.toml",
+            r#"
+            [post]
+            description = "Blog post"
+            fields.title = { type = "str" }
+            fields.author = { type = "relation", schema = { name = "user" } }
+            "#,
+        );
+        write(
+            "users/schema.toml",
+            r#"
+            [user]
+            description = "User"
+            fields.name = { type = "str" }
+            "#,
+        );
+        write("broken/schema.toml", "[user");
+
+        let context = Context::new(
+            MemoryStore::default(),
+            &PathBuf::from("schema.toml"),
+            &PathBuf::from("schema.lock"),
+            &dir.path().join("fishy.toml"),
+            SchemaFormat::Toml,
+        );
+
+        // Members are read without their schema files, a broken member doesn't stop the others
+        let members = read_workspace(&context).unwrap().unwrap();
+        let names = |members: &[&Member]| -> Vec<String> {
+            members
+                .iter()
+                .map(|member| member.path.file_name().unwrap().to_string_lossy().into())
+                .collect()
+        };
+        assert_eq!(
+            names(&members.iter().collect::<Vec<_>>()),
+            ["blog", "users", "broken"]
+        );
+
+        let err = workspace_check(&members).unwrap_err();
+        assert_eq!(err.to_string(), "Found problems in 1 of 3 members");
+
+        let err = sort_members(&members).unwrap_err();
+        assert!(err.to_string().contains("broken"));
+
+        let members: Vec<Member> = members
+            .into_iter()
+            .filter(|member| !member.path.ends_with("broken"))
+            .collect();
+        workspace_check(&members).unwrap();
+        assert_eq!(names(&sort_members(&members).unwrap()), ["users", "blog"]);
+
+        // The relation of the blog resolves to the user schema of the other member
+        workspace_update(context.clone(), &members, |_| Ok(Box::new(KeyPair::new())))
+            .await
+            .unwrap();
+        assert!(workspace_status(context, &members).await.unwrap());
+    }
+}
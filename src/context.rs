@@ -5,6 +5,8 @@ use std::sync::Arc;
 use p2panda_rs::storage_provider::traits::{DocumentStore, EntryStore, LogStore, OperationStore};
 use p2panda_rs::test_utils::memory_store::MemoryStore;
 
+use crate::files::SchemaFormat;
+
 pub struct InnerContext<S>
 where
     S: EntryStore + OperationStore + LogStore + DocumentStore,
@@ -12,6 +14,8 @@ where
     pub store: S,
     pub schema_path: PathBuf,
     pub lock_path: PathBuf,
+    pub config_path: PathBuf,
+    pub schema_format: SchemaFormat,
 }
 
 pub struct Context<S: EntryStore + OperationStore + LogStore + DocumentStore = MemoryStore>(
@@ -22,11 +26,19 @@ impl<S> Context<S>
 where
     S: EntryStore + OperationStore + LogStore + DocumentStore,
 {
-    pub fn new(store: S, schema_path: &PathBuf, lock_path: &PathBuf) -> Self {
+    pub fn new(
+        store: S,
+        schema_path: &PathBuf,
+        lock_path: &PathBuf,
+        config_path: &PathBuf,
+        schema_format: SchemaFormat,
+    ) -> Self {
         Self(Arc::new(InnerContext {
             store,
             schema_path: schema_path.clone(),
             lock_path: lock_path.clone(),
+            config_path: config_path.clone(),
+            schema_format,
         }))
     }
 }
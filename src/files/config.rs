@@ -0,0 +1,122 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+/// Options for connecting to a node, used by `publish`.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NodeConfig {
+    /// GraphQL endpoint of the node.
+    pub endpoint: Option<String>,
+
+    /// Additional HTTP headers sent with every request.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+
+    /// Name of the environment variable holding a bearer token.
+    pub token_env: Option<String>,
+
+    /// Path to a file holding a bearer token.
+    pub token_file: Option<PathBuf>,
+
+    /// Path to a PEM encoded CA certificate bundle to verify the node with.
+    pub ca_cert: Option<PathBuf>,
+
+    /// Path to a PEM encoded client certificate for mutual TLS.
+    pub client_cert: Option<PathBuf>,
+
+    /// Path to the PEM encoded private key of the client certificate.
+    pub client_key: Option<PathBuf>,
+
+    /// Request timeout in seconds.
+    pub timeout: Option<u64>,
+}
+
+impl NodeConfig {
+    /// Fills all options which are not set with the ones from the given config.
+    ///
+    /// The token source is treated as one option, so a token file given here also replaces a
+    /// token environment variable of the other config and vice versa.
+    pub fn merge(self, other: NodeConfig) -> Self {
+        let mut headers = other.headers;
+        headers.extend(self.headers);
+
+        let (token_env, token_file) = if self.token_env.is_some() || self.token_file.is_some() {
+            (self.token_env, self.token_file)
+        } else {
+            (other.token_env, other.token_file)
+        };
+
+        Self {
+            endpoint: self.endpoint.or(other.endpoint),
+            headers,
+            token_env,
+            token_file,
+            ca_cert: self.ca_cert.or(other.ca_cert),
+            client_cert: self.client_cert.or(other.client_cert),
+            client_key: self.client_key.or(other.client_key),
+            timeout: self.timeout.or(other.timeout),
+        }
+    }
+
+    /// Resolves all relative file paths against the given directory.
+    pub fn relative_to(self, base: &Path) -> Self {
+        let resolve = |path: Option<PathBuf>| path.map(|path| base.join(path));
+
+        Self {
+            token_file: resolve(self.token_file),
+            ca_cert: resolve(self.ca_cert),
+            client_cert: resolve(self.client_cert),
+            client_key: resolve(self.client_key),
+            ..self
+        }
+    }
+}
+
+/// Schema folders which are managed together, each with its own schema, lock and key file.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WorkspaceConfig {
+    /// Paths of the member folders, relative to the config file.
+    pub members: Vec<PathBuf>,
+}
+
+/// Optional project configuration, usually stored in a `fishy.toml` file.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    #[serde(default)]
+    pub node: NodeConfig,
+
+    pub workspace: Option<WorkspaceConfig>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::NodeConfig;
+
+    #[test]
+    fn merges_token_source() {
+        let config = NodeConfig {
+            endpoint: Some("https://example.org/graphql".into()),
+            token_env: Some("FISHY_TOKEN".into()),
+            ..NodeConfig::default()
+        };
+
+        let args = NodeConfig {
+            token_file: Some(PathBuf::from("token.txt")),
+            ..NodeConfig::default()
+        };
+
+        let merged = args.merge(config.clone());
+        assert_eq!(merged.endpoint, config.endpoint);
+        assert_eq!(merged.token_env, None);
+        assert_eq!(merged.token_file, Some(PathBuf::from("token.txt")));
+
+        let merged = NodeConfig::default().merge(config);
+        assert_eq!(merged.token_env, Some("FISHY_TOKEN".into()));
+    }
+}
@@ -0,0 +1,117 @@
+use std::fmt;
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::files::SchemaFile;
+
+/// Formats a schema file can be written in, all sharing the same data model.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SchemaFormat {
+    Toml,
+    Yaml,
+    Json,
+}
+
+impl SchemaFormat {
+    /// Extensions of the files which are read as schema files.
+    pub const EXTENSIONS: [&str; 4] = ["toml", "yaml", "yml", "json"];
+
+    /// Detects the format by the file extension, falling back to TOML.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("yaml" | "yml") => Self::Yaml,
+            Some("json") => Self::Json,
+            _ => Self::Toml,
+        }
+    }
+
+    /// Extension of files in this format.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Toml => "toml",
+            Self::Yaml => "yaml",
+            Self::Json => "json",
+        }
+    }
+
+    /// Parses the content into a table, before it is turned into a schema file.
+    pub fn parse(&self, content: &str) -> Result<toml::Table> {
+        let value: serde_json::Value = match self {
+            Self::Toml => return Ok(toml::from_str(content)?),
+            Self::Yaml => serde_yaml::from_str(content)?,
+            Self::Json => serde_json::from_str(content)?,
+        };
+
+        match find_null(&value, "") {
+            Some(path) if path.is_empty() => bail!("Expected a table of schemas, found null"),
+            Some(path) => bail!("Null values are not supported, found one at '{path}'"),
+            None => Ok(serde_json::from_value(value)?),
+        }
+    }
+
+    pub fn deserialize(&self, content: &str) -> Result<SchemaFile> {
+        Ok(toml::Value::Table(self.parse(content)?).try_into()?)
+    }
+
+    /// Writes a schema file, or any value sharing its data model.
+    pub fn serialize(&self, schema_file: &impl Serialize) -> Result<String> {
+        let content = match self {
+            Self::Toml => toml::to_string_pretty(schema_file)?,
+            Self::Yaml => serde_yaml::to_string(schema_file)?,
+            Self::Json => format!("{}\n", serde_json::to_string_pretty(schema_file)?),
+        };
+
+        Ok(content)
+    }
+}
+
+/// Returns the path of the first null value, which can't be represented in TOML.
+fn find_null(value: &serde_json::Value, path: &str) -> Option<String> {
+    match value {
+        serde_json::Value::Null => Some(path.to_string()),
+        serde_json::Value::Array(values) => values
+            .iter()
+            .enumerate()
+            .find_map(|(index, value)| find_null(value, &format!("{path}[{index}]"))),
+        serde_json::Value::Object(map) => map.iter().find_map(|(key, value)| {
+            let path = if path.is_empty() {
+                key.clone()
+            } else {
+                format!("{path}.{key}")
+            };
+            find_null(value, &path)
+        }),
+        _ => None,
+    }
+}
+
+impl fmt::Display for SchemaFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Toml => write!(f, "TOML"),
+            Self::Yaml => write!(f, "YAML"),
+            Self::Json => write!(f, "JSON"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SchemaFormat;
+
+    #[test]
+    fn rejects_null_values() {
+        let content = "post:\n  description: \"\"\n  fields:\n    title: ~\n";
+        let err = SchemaFormat::Yaml.parse(content).unwrap_err();
+        assert!(err.to_string().contains("'post.fields.title'"), "{err}");
+
+        let err = SchemaFormat::Json.parse("null").unwrap_err();
+        assert!(err.to_string().contains("found null"), "{err}");
+
+        let content = r#"{ "post": { "description": "", "fields": {} } }"#;
+        assert!(SchemaFormat::Json.parse(content).is_ok());
+    }
+}
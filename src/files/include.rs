@@ -0,0 +1,231 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context as ErrorContext, Result};
+
+use crate::files::{expand_mixins, expand_objects, Mixins, SchemaFile, SchemaFormat, MIXINS_KEY};
+
+/// Key of the list of files which are included into a schema file.
+pub const INCLUDE_KEY: &str = "include";
+
+/// Splits a schema file into the files it includes, the mixins and the schemas it defines.
+fn parse_schema_file(
+    content: &str,
+    format: SchemaFormat,
+) -> Result<(Vec<String>, Mixins, SchemaFile)> {
+    let mut table = format.parse(content)?;
+
+    let includes = match table.remove(INCLUDE_KEY) {
+        Some(value) => value
+            .try_into()
+            .with_context(|| "'include' must be a list of paths")?,
+        None => Vec::new(),
+    };
+
+    let mixins = match table.remove(MIXINS_KEY) {
+        Some(value) => value
+            .try_into()
+            .with_context(|| "'mixins' must be a table of fields by mixin name")?,
+        None => Mixins::new(),
+    };
+
+    let schema_file: SchemaFile = toml::Value::Table(table).try_into()?;
+
+    Ok((includes, mixins, schema_file))
+}
+
+/// Expands an include entry into the files it refers to.
+///
+/// Folders include all schema files inside of them and glob patterns all matching files, both
+/// in alphabetical order.
+pub fn expand_include(base: &Path, include: &str) -> Result<Vec<PathBuf>> {
+    let path = base.join(include);
+    let is_dir = path.is_dir();
+
+    let pattern = if is_dir {
+        path.join("*")
+    } else if include.contains(['*', '?', '[']) {
+        path
+    } else {
+        return Ok(vec![path]);
+    };
+
+    let mut paths = glob::glob(&pattern.to_string_lossy())
+        .with_context(|| format!("Invalid include pattern '{include}'"))?
+        .collect::<Result<Vec<PathBuf>, _>>()?;
+
+    if is_dir {
+        paths.retain(|path| {
+            path.extension()
+                .and_then(|extension| extension.to_str())
+                .map_or(false, |extension| {
+                    SchemaFormat::EXTENSIONS.contains(&extension)
+                })
+        });
+    }
+
+    paths.sort();
+
+    Ok(paths)
+}
+
+/// Merges a schema file and all files it includes.
+#[derive(Default)]
+struct Reader {
+    schema_file: SchemaFile,
+    mixins: Mixins,
+
+    /// Files defining the schemas and mixins, to report duplicates.
+    sources: BTreeMap<String, PathBuf>,
+
+    /// Files which are currently read, to detect cycles.
+    stack: Vec<PathBuf>,
+
+    /// All files which were read.
+    paths: Vec<PathBuf>,
+}
+
+impl Reader {
+    fn add_source(&mut self, name: String, path: &Path) -> Result<()> {
+        if let Some(source) = self.sources.get(&name) {
+            bail!(
+                "{name} is defined in both {} and {}",
+                source.display(),
+                path.display()
+            );
+        }
+
+        self.sources.insert(name, path.to_path_buf());
+
+        Ok(())
+    }
+
+    fn read(&mut self, path: &Path, format: SchemaFormat) -> Result<()> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Could not read {}", path.display()))?;
+        let (includes, mixins, schema_file) = parse_schema_file(&content, format)
+            .with_context(|| format!("Invalid {} format", path.display()))?;
+
+        // Check for cycles first, the schemas of the file were registered already otherwise
+        let canonical_path = fs::canonicalize(path)?;
+        if self.stack.contains(&canonical_path) {
+            bail!("{} includes itself", path.display());
+        }
+
+        for (schema_name, schema_item) in schema_file {
+            self.add_source(format!("Schema {schema_name}"), path)?;
+            self.schema_file.insert(schema_name, schema_item);
+        }
+
+        for (mixin_name, fields) in mixins {
+            self.add_source(format!("Mixin {mixin_name}"), path)?;
+            self.mixins.insert(mixin_name, fields);
+        }
+
+        self.stack.push(canonical_path);
+        self.paths.push(path.to_path_buf());
+
+        let base = path.parent().unwrap_or(Path::new(""));
+        for include in includes {
+            for included_path in expand_include(base, &include)? {
+                let format = SchemaFormat::from_path(&included_path);
+                self.read(&included_path, format)?;
+            }
+        }
+
+        self.stack.pop();
+
+        Ok(())
+    }
+}
+
+/// Reads the mixins and schemas of a schema file and all files it includes, without expanding
+/// the mixins.
+pub fn read_schema_definitions(path: &Path, format: SchemaFormat) -> Result<(Mixins, SchemaFile)> {
+    let mut reader = Reader::default();
+    reader.read(path, format)?;
+    Ok((reader.mixins, reader.schema_file))
+}
+
+/// Reads a schema file together with all files it includes, merged into one.
+///
+/// Paths of the `include` list are relative to the file listing them, their format is detected by
+/// the file extension. Schemas which extend mixins or other schemas contain all inherited fields,
+/// nested fields are turned into relations to generated schemas.
+pub fn read_schema_file(path: &Path, format: SchemaFormat) -> Result<SchemaFile> {
+    let (mixins, schema_file) = read_schema_definitions(path, format)?;
+    expand_objects(expand_mixins(schema_file, &mixins)?)
+}
+
+/// Returns the path of the schema file followed by the paths of all files it includes.
+pub fn schema_file_paths(path: &Path, format: SchemaFormat) -> Result<Vec<PathBuf>> {
+    let mut reader = Reader::default();
+    reader.read(path, format)?;
+    Ok(reader.paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::read_schema_file;
+    use crate::files::SchemaFormat;
+
+    fn schema(name: &str) -> String {
+        format!("[{name}]\ndescription = \"\"\n\n[{name}.fields]\ntitle = {{ type = \"str\" }}\n")
+    }
+
+    #[test]
+    fn includes_files() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("schemas")).unwrap();
+
+        fs::write(
+            dir.path().join("schema.toml"),
+            format!("include = [\"schemas\"]\n\n{}", schema("post")),
+        )
+        .unwrap();
+        fs::write(dir.path().join("schemas/comment.toml"), schema("comment")).unwrap();
+
+        let schema_path = dir.path().join("schema.toml");
+        let schema_file = read_schema_file(&schema_path, SchemaFormat::Toml).unwrap();
+        assert_eq!(schema_file.iter().count(), 2);
+
+        fs::write(dir.path().join("schemas/post.toml"), schema("post")).unwrap();
+
+        let err = read_schema_file(&schema_path, SchemaFormat::Toml).unwrap_err();
+        assert!(err.to_string().contains("schema.toml and"));
+        assert!(err.to_string().contains("post.toml"));
+    }
+
+    #[test]
+    fn detects_include_cycles() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let schema_path = dir.path().join("schema.toml");
+        fs::write(
+            &schema_path,
+            format!("include = [\"schema.toml\"]\n\n{}", schema("post")),
+        )
+        .unwrap();
+
+        let err = read_schema_file(&schema_path, SchemaFormat::Toml).unwrap_err();
+        assert!(err.to_string().contains("includes itself"), "{err}");
+
+        let a_path = dir.path().join("a.toml");
+        fs::write(
+            &a_path,
+            format!("include = [\"b.toml\"]\n\n{}", schema("post")),
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("b.toml"),
+            format!("include = [\"a.toml\"]\n\n{}", schema("comment")),
+        )
+        .unwrap();
+
+        let err = read_schema_file(&a_path, SchemaFormat::Toml).unwrap_err();
+        assert!(err.to_string().contains("a.toml includes itself"), "{err}");
+    }
+}
@@ -0,0 +1,24 @@
+use serde::{Deserialize, Serialize};
+
+/// Private key encrypted with a passphrase.
+///
+/// The encryption key is derived from the passphrase with Argon2id, the private key itself is
+/// encrypted with ChaCha20-Poly1305. All binary values are hex encoded.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EncryptedKeyFile {
+    pub version: u64,
+    pub kdf: KdfParams,
+    pub nonce: String,
+    pub ciphertext: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KdfParams {
+    pub algorithm: String,
+    pub salt: String,
+    pub m_cost: u32,
+    pub t_cost: u32,
+    pub p_cost: u32,
+}
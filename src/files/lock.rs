@@ -1,10 +1,17 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+use anyhow::{bail, Context as ErrorContext, Result};
 use p2panda_rs::entry::traits::AsEncodedEntry;
 use p2panda_rs::entry::EncodedEntry;
 use p2panda_rs::hash::Hash;
 use p2panda_rs::operation::EncodedOperation;
+use p2panda_rs::schema::{SchemaId, SchemaName};
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Commit {
     pub entry_hash: Hash,
     pub entry: EncodedEntry,
@@ -21,17 +28,187 @@ impl Commit {
     }
 }
 
+/// Version of the lock files written by fishy.
+pub const LOCK_FILE_VERSION: u64 = 2;
+
+/// Summary of the commits of a lock file.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LockHeader {
+    /// Public keys of all authors who signed commits, in the order they first signed one.
+    pub authors: Vec<String>,
+
+    /// Version of fishy which wrote the lock file.
+    pub fishy_version: String,
+
+    /// Versions of the system schemas used by the commits.
+    pub system_schemas: BTreeMap<String, u8>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct LockFile {
     pub version: u64,
-    pub commits: Option<Vec<Commit>>,
+    pub header: LockHeader,
+
+    /// Current id of every schema defined by the commits, by schema name.
+    #[serde(default)]
+    pub schemas: BTreeMap<SchemaName, SchemaId>,
+
+    #[serde(default)]
+    pub commits: Vec<Commit>,
 }
 
 impl LockFile {
-    pub fn new(commits: Vec<Commit>) -> Self {
+    pub fn new(
+        header: LockHeader,
+        schemas: BTreeMap<SchemaName, SchemaId>,
+        commits: Vec<Commit>,
+    ) -> Self {
         Self {
-            version: 1,
-            commits: Some(commits),
+            version: LOCK_FILE_VERSION,
+            header,
+            schemas,
+            commits,
+        }
+    }
+}
+
+/// Version of a lock file, read first to know which format the rest of the file has.
+#[derive(Debug, Deserialize)]
+pub struct LockFileVersion {
+    pub version: u64,
+}
+
+/// Lock file of version 1, which only contains the commits.
+#[derive(Debug, Deserialize)]
+pub struct LockFileV1 {
+    pub commits: Option<Vec<Commit>>,
+}
+
+/// Reads the lock file, an empty one is returned if it doesn't exist yet.
+///
+/// Lock files of version 1 keep their version and come without header and schema index, they are
+/// written in the current format the next time the lock file changes. The header and index are
+/// taken as they are, see `verify_lock_file` to check them against the commits.
+pub fn read_lock_file(path: &Path) -> Result<LockFile> {
+    let file_str = match fs::read_to_string(path) {
+        Ok(file_str) => file_str,
+        Err(err) if err.kind() == ErrorKind::NotFound => {
+            return Ok(LockFile::new(
+                LockHeader::default(),
+                BTreeMap::new(),
+                Vec::new(),
+            ))
         }
+        Err(err) => {
+            return Err(err).with_context(|| format!("Could not read {} file", path.display()))
+        }
+    };
+
+    let LockFileVersion { version } = toml::from_str(&file_str)
+        .with_context(|| format!("Invalid {} file, its version is missing", path.display()))?;
+
+    match version {
+        1 => {
+            let lock_file: LockFileV1 = toml::from_str(&file_str)
+                .with_context(|| format!("Invalid {} file", path.display()))?;
+
+            Ok(LockFile {
+                version,
+                header: LockHeader::default(),
+                schemas: BTreeMap::new(),
+                commits: lock_file.commits.unwrap_or_default(),
+            })
+        }
+        LOCK_FILE_VERSION => {
+            toml::from_str(&file_str).with_context(|| format!("Invalid {} file", path.display()))
+        }
+        version if version > LOCK_FILE_VERSION => bail!(
+            "{} has version {version}, but this version of fishy only supports lock files up \
+            to version {LOCK_FILE_VERSION}, please update fishy",
+            path.display()
+        ),
+        version => bail!("{} has unknown version {version}", path.display()),
+    }
+}
+
+/// Writes the lock file.
+pub fn write_lock_file(path: &Path, lock_file: &LockFile) -> Result<()> {
+    let lock_file_str = format!(
+        "{}\n\n{}",
+        "# This file is automatically generated by fishy.\n# It is not intended for manual editing.",
+        toml::to_string_pretty(lock_file)?
+    );
+
+    fs::write(path, lock_file_str)
+        .with_context(|| format!("Could not create {} file", path.display()))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::fs;
+
+    use p2panda_rs::entry::EncodedEntry;
+    use p2panda_rs::operation::EncodedOperation;
+
+    use super::{read_lock_file, write_lock_file, Commit, LockFile, LockHeader, LOCK_FILE_VERSION};
+
+    #[test]
+    fn reads_lock_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("schema.lock");
+
+        // Reading doesn't decode the commits
+        let commits = vec![Commit::new(
+            &EncodedEntry::from_bytes(&[1, 2, 3]),
+            &EncodedOperation::from_bytes(&[4, 5, 6]),
+        )];
+
+        let header = LockHeader {
+            authors: vec!["author".into()],
+            fishy_version: "0.1.0".into(),
+            system_schemas: BTreeMap::from([("schema_definition".into(), 1)]),
+        };
+        let schemas = BTreeMap::from([(
+            "post".parse().unwrap(),
+            "post_0020c65567ae37efea293e34a9c7d13f8f2bf23dbdc3b5c7b9ab46293111c48fc78b"
+                .parse()
+                .unwrap(),
+        )]);
+        write_lock_file(
+            &path,
+            &LockFile::new(header.clone(), schemas.clone(), commits.clone()),
+        )
+        .unwrap();
+
+        let lock_file = read_lock_file(&path).unwrap();
+        assert_eq!(lock_file.version, LOCK_FILE_VERSION);
+        assert_eq!(lock_file.header, header);
+        assert_eq!(lock_file.schemas, schemas);
+        assert_eq!(lock_file.commits[0].entry_hash, commits[0].entry_hash);
+
+        // Version 1 only contains the commits
+        let mut lock_file_v1 = toml::Table::new();
+        lock_file_v1.insert("version".into(), 1.into());
+        lock_file_v1.insert("commits".into(), toml::Value::try_from(&commits).unwrap());
+        fs::write(&path, toml::to_string(&lock_file_v1).unwrap()).unwrap();
+
+        let lock_file = read_lock_file(&path).unwrap();
+        assert_eq!(lock_file.version, 1);
+        assert!(lock_file.schemas.is_empty());
+        assert_eq!(lock_file.commits.len(), 1);
+
+        fs::write(&path, "version = 3\n").unwrap();
+        let err = read_lock_file(&path).unwrap_err();
+        assert!(err.to_string().contains("please update fishy"));
+
+        // Only a missing file counts as empty lock file
+        assert!(read_lock_file(&dir.path().join("missing.lock"))
+            .unwrap()
+            .commits
+            .is_empty());
+        assert!(read_lock_file(dir.path()).is_err());
     }
 }
@@ -0,0 +1,231 @@
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Result};
+use p2panda_rs::schema::{FieldName, SchemaName};
+
+use crate::files::{SchemaField, SchemaFields, SchemaFile, SchemaItem};
+
+/// Key of the groups of fields which schemas can extend.
+pub const MIXINS_KEY: &str = "mixins";
+
+/// Groups of fields which are not published themselves, by their name.
+pub type Mixins = BTreeMap<String, SchemaFields>;
+
+/// Fields together with the name of the mixin or schema defining them.
+type ResolvedFields = BTreeMap<FieldName, (SchemaField, String)>;
+
+fn merge(name: &str, resolved: &mut ResolvedFields, fields: ResolvedFields) -> Result<()> {
+    for (field_name, (field, origin)) in fields {
+        match resolved.get(&field_name) {
+            // The same field can be inherited on several ways
+            Some((existing, existing_origin))
+                if *existing_origin == origin && *existing == field => {}
+            Some((_, existing_origin)) => bail!(
+                "Field '{field_name}' of {name} is defined by both {existing_origin} and {origin}"
+            ),
+            None => {
+                resolved.insert(field_name, (field, origin));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Collects the fields of a mixin or schema, including the ones of everything it extends.
+fn resolve(
+    name: &str,
+    schema_file: &SchemaFile,
+    mixins: &Mixins,
+    stack: &mut Vec<String>,
+) -> Result<ResolvedFields> {
+    if stack.iter().any(|extending| extending == name) {
+        bail!(
+            "Schema {name} extends itself: {} -> {name}",
+            stack.join(" -> ")
+        );
+    }
+
+    let schema_item = schema_file
+        .iter()
+        .find(|(schema_name, _)| schema_name.to_string() == name)
+        .map(|(_, schema_item)| schema_item);
+
+    let mut resolved = ResolvedFields::new();
+
+    match (mixins.get(name), schema_item) {
+        (Some(_), Some(_)) => bail!("'{name}' is both a mixin and a schema"),
+        (Some(fields), None) => {
+            for (field_name, field) in fields.iter() {
+                resolved.insert(field_name.clone(), (field.clone(), name.to_string()));
+            }
+        }
+        (None, Some(schema_item)) => {
+            stack.push(name.to_string());
+            for base in &schema_item.extends {
+                let base_fields = resolve(base, schema_file, mixins, stack)?;
+                merge(name, &mut resolved, base_fields)?;
+            }
+            stack.pop();
+
+            let mut own_fields = ResolvedFields::new();
+            for (field_name, field) in schema_item.fields.iter() {
+                own_fields.insert(field_name.clone(), (field.clone(), name.to_string()));
+            }
+            merge(name, &mut resolved, own_fields)?;
+        }
+        (None, None) => bail!(
+            "Schema {} extends unknown mixin or schema '{name}'",
+            stack.last().map_or("", String::as_str)
+        ),
+    }
+
+    Ok(resolved)
+}
+
+/// Returns the name of the mixin or schema defining each field of every schema, including the
+/// inherited fields.
+pub fn field_origins(
+    schema_file: &SchemaFile,
+    mixins: &Mixins,
+) -> Result<BTreeMap<SchemaName, BTreeMap<FieldName, String>>> {
+    schema_file
+        .iter()
+        .map(|(schema_name, _)| {
+            let resolved = resolve(
+                &schema_name.to_string(),
+                schema_file,
+                mixins,
+                &mut Vec::new(),
+            )?;
+            let origins = resolved
+                .into_iter()
+                .map(|(field_name, (_, origin))| (field_name, origin))
+                .collect();
+
+            Ok((schema_name.clone(), origins))
+        })
+        .collect()
+}
+
+/// Adds the fields of all extended mixins and schemas to the schemas extending them.
+pub fn expand_mixins(schema_file: SchemaFile, mixins: &Mixins) -> Result<SchemaFile> {
+    let mut expanded = SchemaFile::new();
+
+    for (schema_name, schema_item) in schema_file.iter() {
+        let mut fields = SchemaFields::new();
+        for (field_name, (field, _)) in resolve(
+            &schema_name.to_string(),
+            &schema_file,
+            mixins,
+            &mut Vec::new(),
+        )? {
+            fields.insert(field_name, field);
+        }
+
+        if fields.len() == 0 {
+            bail!("Schema {schema_name} doesn't have any fields");
+        }
+
+        expanded.insert(
+            schema_name.clone(),
+            SchemaItem {
+                description: schema_item.description.clone(),
+                extends: Vec::new(),
+                fields,
+            },
+        );
+    }
+
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use p2panda_rs::schema::SchemaName;
+
+    use super::{expand_mixins, field_origins, Mixins};
+    use crate::files::SchemaFormat;
+
+    #[test]
+    fn expands_mixins() {
+        let mixins: Mixins = toml::from_str(
+            r#"
+            [timestamps]
+            created_at = { type = "int" }
+            updated_at = { type = "int" }
+            "#,
+        )
+        .unwrap();
+
+        let schema_file = SchemaFormat::Toml
+            .deserialize(
+                r#"
+                [base]
+                description = ""
+                extends = "timestamps"
+                fields = { author_name = { type = "str" } }
+
+                [post]
+                description = ""
+                extends = ["base", "timestamps"]
+                fields = { title = { type = "str" } }
+                "#,
+            )
+            .unwrap();
+
+        let post_name: SchemaName = "post".parse().unwrap();
+        let origins = field_origins(&schema_file, &mixins).unwrap();
+        let post_origins: Vec<(&str, &str)> = origins[&post_name]
+            .iter()
+            .map(|(field_name, origin)| (field_name.as_str(), origin.as_str()))
+            .collect();
+        assert_eq!(
+            post_origins,
+            [
+                ("author_name", "base"),
+                ("created_at", "timestamps"),
+                ("title", "post"),
+                ("updated_at", "timestamps")
+            ]
+        );
+
+        let expanded = expand_mixins(schema_file, &mixins).unwrap();
+        let (_, post) = expanded
+            .iter()
+            .find(|(name, _)| name.to_string() == "post")
+            .unwrap();
+        let field_names: Vec<&String> = post.fields.iter().map(|(name, _)| name).collect();
+        assert_eq!(
+            field_names,
+            ["author_name", "created_at", "title", "updated_at"]
+        );
+
+        let conflicting = SchemaFormat::Toml
+            .deserialize(
+                r#"
+                [post]
+                description = ""
+                extends = "timestamps"
+                fields = { created_at = { type = "str" } }
+                "#,
+            )
+            .unwrap();
+        let err = expand_mixins(conflicting, &mixins).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Field 'created_at' of post is defined by both timestamps and post"
+        );
+
+        let cyclic = SchemaFormat::Toml
+            .deserialize(
+                r#"
+                a = { description = "", extends = "b" }
+                b = { description = "", extends = "a" }
+                "#,
+            )
+            .unwrap();
+        let err = expand_mixins(cyclic, &Mixins::new()).unwrap_err();
+        assert_eq!(err.to_string(), "Schema a extends itself: a -> b -> a");
+    }
+}
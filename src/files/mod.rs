@@ -1,7 +1,24 @@
+mod config;
+mod format;
+mod include;
+mod key;
 mod lock;
+mod mixin;
+mod object;
+mod plan;
 mod schema;
 
-pub use lock::{Commit, LockFile};
+pub use config::{ConfigFile, NodeConfig, WorkspaceConfig};
+pub use format::SchemaFormat;
+pub use include::{
+    expand_include, read_schema_definitions, read_schema_file, schema_file_paths, INCLUDE_KEY,
+};
+pub use key::{EncryptedKeyFile, KdfParams};
+pub use lock::{read_lock_file, write_lock_file, Commit, LockFile, LockHeader, LOCK_FILE_VERSION};
+pub use mixin::{expand_mixins, field_origins, Mixins, MIXINS_KEY};
+pub use object::{expand_objects, object_schema_name};
+pub use plan::{PlanFile, PlannedOperation};
 pub use schema::{
-    FieldType, RelationId, RelationType, SchemaField, SchemaFields, SchemaFile, SchemaItem,
+    FieldConstraints, FieldType, RelationId, RelationSchema, RelationSource, RelationType,
+    SchemaField, SchemaFields, SchemaFile, SchemaItem,
 };
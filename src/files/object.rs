@@ -0,0 +1,175 @@
+use anyhow::{bail, Context as ErrorContext, Result};
+use p2panda_rs::schema::{SchemaDescription, SchemaName};
+
+use crate::files::{
+    RelationId, RelationSchema, RelationType, SchemaField, SchemaFields, SchemaFile, SchemaItem,
+};
+
+/// Name of the schema which is generated for the nested fields of a field.
+pub fn object_schema_name(schema_name: &SchemaName, field_name: &str) -> Result<SchemaName> {
+    SchemaName::new(&format!("{schema_name}_{field_name}")).with_context(|| {
+        format!("Invalid schema name for the nested field '{field_name}' of {schema_name}")
+    })
+}
+
+/// Replaces nested fields by relations to generated schemas, which are added to `expanded`.
+fn expand_fields(
+    schema_name: &SchemaName,
+    fields: &SchemaFields,
+    schema_file: &SchemaFile,
+    expanded: &mut SchemaFile,
+) -> Result<SchemaFields> {
+    let mut expanded_fields = SchemaFields::new();
+
+    for (field_name, field) in fields.iter() {
+        let (field_type, object_fields) = match field {
+            SchemaField::Object { field_type, fields } => (field_type, fields),
+            field => {
+                expanded_fields.insert(field_name.clone(), field.clone());
+                continue;
+            }
+        };
+
+        if !matches!(
+            field_type,
+            RelationType::Relation | RelationType::RelationList
+        ) {
+            bail!(
+                "Nested field '{field_name}' of {schema_name} has to be a relation or relation_list"
+            );
+        }
+
+        let object_name = object_schema_name(schema_name, field_name)?;
+        let is_defined = schema_file
+            .iter()
+            .chain(expanded.iter())
+            .any(|(name, _)| *name == object_name);
+        if is_defined {
+            bail!(
+                "Schema {object_name} is generated for the nested field '{field_name}' of \
+                {schema_name}, but it is already defined"
+            );
+        }
+
+        let object_fields = expand_fields(&object_name, object_fields, schema_file, expanded)?;
+        if object_fields.len() == 0 {
+            bail!("Nested field '{field_name}' of {schema_name} doesn't have any fields");
+        }
+
+        expanded.insert(
+            object_name.clone(),
+            SchemaItem {
+                description: SchemaDescription::new(&format!(
+                    "Nested field '{field_name}' of {schema_name}"
+                ))?,
+                extends: Vec::new(),
+                fields: object_fields,
+            },
+        );
+
+        expanded_fields.insert(
+            field_name.clone(),
+            SchemaField::Relation {
+                field_type: field_type.clone(),
+                schema: RelationSchema {
+                    id: RelationId::Name(object_name),
+                    external: None,
+                },
+            },
+        );
+    }
+
+    Ok(expanded_fields)
+}
+
+/// Turns nested fields into generated schemas which the fields relate to.
+///
+/// The generated schema of field `address` of schema `user` is named `user_address`.
+pub fn expand_objects(schema_file: SchemaFile) -> Result<SchemaFile> {
+    let mut expanded = SchemaFile::new();
+
+    for (schema_name, schema_item) in schema_file.iter() {
+        let fields = expand_fields(
+            schema_name,
+            &schema_item.fields,
+            &schema_file,
+            &mut expanded,
+        )?;
+
+        expanded.insert(
+            schema_name.clone(),
+            SchemaItem {
+                description: schema_item.description.clone(),
+                extends: schema_item.extends.clone(),
+                fields,
+            },
+        );
+    }
+
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::expand_objects;
+    use crate::files::{RelationId, RelationSchema, RelationType, SchemaField, SchemaFormat};
+
+    #[test]
+    fn expands_objects() {
+        let schema_file = SchemaFormat::Toml
+            .deserialize(
+                r#"
+                [user]
+                description = "User"
+
+                [user.fields]
+                name = { type = "str" }
+
+                [user.fields.address]
+                type = "relation"
+                fields.street = { type = "str" }
+                fields.geo = { type = "relation_list", fields = { lat = { type = "float" } } }
+                "#,
+            )
+            .unwrap();
+
+        let expanded = expand_objects(schema_file).unwrap();
+        let names: Vec<String> = expanded.iter().map(|(name, _)| name.to_string()).collect();
+        assert_eq!(names, ["user", "user_address", "user_address_geo"]);
+
+        let (_, address) = expanded
+            .iter()
+            .find(|(name, _)| name.to_string() == "user_address")
+            .unwrap();
+        let (_, geo) = address
+            .fields
+            .iter()
+            .find(|(name, _)| *name == "geo")
+            .unwrap();
+        assert_eq!(
+            geo,
+            &SchemaField::Relation {
+                field_type: RelationType::RelationList,
+                schema: RelationSchema {
+                    id: RelationId::Name("user_address_geo".parse().unwrap()),
+                    external: None,
+                },
+            }
+        );
+
+        let conflicting = SchemaFormat::Toml
+            .deserialize(
+                r#"
+                [user]
+                description = ""
+                fields.address = { type = "relation", fields = { street = { type = "str" } } }
+
+                [user_address]
+                description = ""
+                fields.street = { type = "str" }
+                "#,
+            )
+            .unwrap();
+        assert!(expand_objects(conflicting).is_err());
+    }
+}
@@ -0,0 +1,43 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use p2panda_rs::schema::{SchemaId, SchemaName};
+use serde::{Deserialize, Serialize};
+
+use crate::files::{Commit, SchemaFile};
+
+/// Operation which is planned to be signed, for reviewing the plan before signing it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PlannedOperation {
+    pub action: String,
+    pub kind: String,
+    pub name: String,
+}
+
+impl fmt::Display for PlannedOperation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} '{}'", self.action, self.kind, self.name)
+    }
+}
+
+/// Portable file to sign schema updates on another machine.
+///
+/// It contains everything needed to create the signed commits without access to the project:
+/// the schema definitions, the ids of related schemas defined elsewhere, the commits already in
+/// the lock file and the expected author.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PlanFile {
+    pub version: u64,
+    pub public_key: String,
+    pub operations: Vec<PlannedOperation>,
+
+    /// Ids of the related schemas which are not defined in the schema file, by their name.
+    #[serde(default)]
+    pub external: BTreeMap<SchemaName, SchemaId>,
+
+    pub schema: SchemaFile,
+    pub commits: Vec<Commit>,
+    pub signed_commits: Option<Vec<Commit>>,
+}
@@ -1,8 +1,8 @@
-use std::collections::btree_map::Iter;
+use std::collections::btree_map::{IntoIter, Iter};
 use std::collections::BTreeMap;
 
 use p2panda_rs::schema::{FieldName, SchemaDescription, SchemaId, SchemaName};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
@@ -53,21 +53,122 @@ pub struct RelationSchema {
     pub external: Option<RelationSource>,
 }
 
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+/// Local rules for the values of a field, used for validation and code generation.
+///
+/// They are not published, changing them doesn't change the schema on the node.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FieldConstraints {
+    /// Allowed values of a string field.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub values: Option<Vec<String>>,
+
+    /// Smallest allowed value of a number field.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min: Option<f64>,
+
+    /// Largest allowed value of a number field.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max: Option<f64>,
+
+    /// Regular expression which the value of a string field has to match.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
+
+    /// The field may be left out by applications.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub optional: bool,
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(untagged, deny_unknown_fields)]
+#[serde(untagged, from = "RawSchemaField")]
 pub enum SchemaField {
     Field {
         #[serde(rename = "type")]
         field_type: FieldType,
+
+        #[serde(flatten)]
+        constraints: FieldConstraints,
     },
     Relation {
         #[serde(rename = "type")]
         field_type: RelationType,
         schema: RelationSchema,
     },
+    /// Nested fields, published as a generated schema the field relates to.
+    Object {
+        #[serde(rename = "type")]
+        field_type: RelationType,
+        fields: SchemaFields,
+    },
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// Field as written in a schema file.
+///
+/// The constraints are listed explicitly, as serde ignores `deny_unknown_fields` next to
+/// `flatten` and misspelled constraints would be accepted otherwise.
+#[derive(Deserialize)]
+#[serde(untagged, deny_unknown_fields)]
+enum RawSchemaField {
+    Field {
+        #[serde(rename = "type")]
+        field_type: FieldType,
+        #[serde(default)]
+        values: Option<Vec<String>>,
+        #[serde(default)]
+        min: Option<f64>,
+        #[serde(default)]
+        max: Option<f64>,
+        #[serde(default)]
+        pattern: Option<String>,
+        #[serde(default)]
+        optional: bool,
+    },
+    Relation {
+        #[serde(rename = "type")]
+        field_type: RelationType,
+        schema: RelationSchema,
+    },
+    Object {
+        #[serde(rename = "type")]
+        field_type: RelationType,
+        fields: SchemaFields,
+    },
+}
+
+impl From<RawSchemaField> for SchemaField {
+    fn from(field: RawSchemaField) -> Self {
+        match field {
+            RawSchemaField::Field {
+                field_type,
+                values,
+                min,
+                max,
+                pattern,
+                optional,
+            } => Self::Field {
+                field_type,
+                constraints: FieldConstraints {
+                    values,
+                    min,
+                    max,
+                    pattern,
+                    optional,
+                },
+            },
+            RawSchemaField::Relation { field_type, schema } => {
+                Self::Relation { field_type, schema }
+            }
+            RawSchemaField::Object { field_type, fields } => Self::Object { field_type, fields },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct SchemaFields(BTreeMap<FieldName, SchemaField>);
 
 impl SchemaFields {
@@ -88,14 +189,39 @@ impl SchemaFields {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Accepts a single name or a list of names.
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(name) => vec![name],
+        OneOrMany::Many(names) => names,
+    })
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct SchemaItem {
     pub description: SchemaDescription,
+
+    /// Mixins or schemas whose fields are added to the fields of this schema.
+    #[serde(
+        default,
+        deserialize_with = "one_or_many",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub extends: Vec<String>,
+
+    #[serde(default)]
     pub fields: SchemaFields,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct SchemaFile(BTreeMap<SchemaName, SchemaItem>);
 
@@ -108,6 +234,10 @@ impl SchemaFile {
         self.0.iter()
     }
 
+    pub fn insert(&mut self, name: SchemaName, item: SchemaItem) {
+        self.0.insert(name, item);
+    }
+
     pub fn add_schema(
         &mut self,
         name: &SchemaName,
@@ -118,25 +248,36 @@ impl SchemaFile {
             name.clone(),
             SchemaItem {
                 description: description.to_owned(),
+                extends: Vec::new(),
                 fields: fields.to_owned(),
             },
         );
     }
 }
 
+impl IntoIterator for SchemaFile {
+    type Item = (SchemaName, SchemaItem);
+    type IntoIter = IntoIter<SchemaName, SchemaItem>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::collections::BTreeMap;
 
     use super::{
-        FieldType, RelationId, RelationSchema, RelationSource, RelationType, SchemaField,
-        SchemaFields, SchemaFile, SchemaItem,
+        FieldConstraints, FieldType, RelationId, RelationSchema, RelationSource, RelationType,
+        SchemaField, SchemaFields, SchemaFile, SchemaItem,
     };
 
     #[test]
     fn lala() {
         let mut schema_item = SchemaItem {
             description: "lala".parse().unwrap(),
+            extends: Vec::new(),
             fields: SchemaFields::new(),
         };
 
@@ -157,6 +298,7 @@ mod tests {
             "another_field".into(),
             SchemaField::Field {
                 field_type: FieldType::String,
+                constraints: FieldConstraints::default(),
             },
         );
 
@@ -165,4 +307,24 @@ mod tests {
         let schema_file_str = toml::to_string_pretty(&schema_file).unwrap();
         println!("{schema_file_str}");
     }
+
+    #[test]
+    fn rejects_unknown_field_keys() {
+        let field: SchemaField = toml::from_str("type = \"str\"\npattern = \"^[a-z]\"").unwrap();
+        assert_eq!(
+            field,
+            SchemaField::Field {
+                field_type: FieldType::String,
+                constraints: FieldConstraints {
+                    pattern: Some("^[a-z]".into()),
+                    ..FieldConstraints::default()
+                },
+            }
+        );
+
+        assert!(toml::from_str::<SchemaField>("type = \"str\"\npatern = \"^[a-z]\"").is_err());
+        assert!(
+            toml::from_str::<SchemaField>("type = \"str\"\nschema = { name = \"post\" }").is_err()
+        );
+    }
 }
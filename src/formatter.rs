@@ -0,0 +1,389 @@
+use anyhow::{Context as ErrorContext, Result};
+use toml_edit::{Decor, Document, InlineTable, Item, RawString, Table, TableLike, Value};
+
+use crate::files::{INCLUDE_KEY, MIXINS_KEY};
+
+/// Order of the keys of a schema, fields come last as their own table.
+const SCHEMA_KEYS: [&str; 2] = ["description", "extends"];
+
+/// Order of the keys of a field.
+const FIELD_KEYS: [&str; 8] = [
+    "type", "schema", "fields", "values", "min", "max", "pattern", "optional",
+];
+
+/// Order of the keys of a related schema.
+const RELATION_SCHEMA_KEYS: [&str; 4] = ["name", "id", "git", "path"];
+
+/// Returns all comment lines of a prefix or suffix, each ending with a newline.
+fn comment_lines(raw: Option<&RawString>) -> String {
+    raw.and_then(|raw| raw.as_str())
+        .unwrap_or_default()
+        .lines()
+        .map(str::trim)
+        .filter(|line| line.starts_with('#'))
+        .map(|line| format!("{line}\n"))
+        .collect()
+}
+
+/// Returns all comments around a table header or key.
+fn decor_comments(decor: Option<&Decor>) -> String {
+    decor.map_or(String::new(), |decor| {
+        comment_lines(decor.prefix()) + &comment_lines(decor.suffix())
+    })
+}
+
+/// Returns the comment behind a value or table header, if there is any.
+fn trailing_comment(decor: &Decor) -> Option<String> {
+    decor
+        .suffix()
+        .and_then(|suffix| suffix.as_str())
+        .map(str::trim)
+        .filter(|suffix| suffix.starts_with('#'))
+        .map(|comment| format!(" {comment}"))
+}
+
+/// Entries of the table, the given keys first and all others in their original order.
+fn ordered_entries<'a>(table: &'a dyn TableLike, order: &[&str]) -> Vec<(&'a str, &'a Item)> {
+    let mut entries: Vec<(&str, &Item)> = table.iter().collect();
+    entries.sort_by_key(|(key, _)| {
+        order
+            .iter()
+            .position(|ordered| ordered == key)
+            .unwrap_or(order.len())
+    });
+    entries
+}
+
+/// Removes the formatting of a value, keeping the comment behind it.
+fn format_value(value: &Value) -> Value {
+    let mut formatted = match value {
+        Value::String(string) => Value::from(string.value().as_str()),
+        // Arrays spanning several lines are kept as they are, they might contain comments
+        Value::Array(array) if !array.to_string().contains('\n') => {
+            let mut array = array.clone();
+            array.fmt();
+            Value::Array(array)
+        }
+        value => value.clone(),
+    };
+
+    formatted.decor_mut().clear();
+    if let Some(comment) = trailing_comment(value.decor()) {
+        formatted.decor_mut().set_suffix(comment);
+    }
+
+    formatted
+}
+
+/// Turns a table of any style into an inline table with ordered keys.
+///
+/// Inline tables can't contain comments, they are collected to be placed above the table.
+fn format_inline_table(item: &Item, order: &[&str], comments: &mut String) -> Result<InlineTable> {
+    let table = item.as_table_like().with_context(|| "Expected a table")?;
+
+    if let Item::Table(table) = item {
+        comments.push_str(&decor_comments(Some(table.decor())));
+    }
+
+    let mut formatted = InlineTable::new();
+
+    for (key, item) in ordered_entries(table, order) {
+        comments.push_str(&decor_comments(table.key_decor(key)));
+
+        let value = if key == "fields" {
+            Value::InlineTable(format_inline_fields(item, comments)?)
+        } else if item.is_table_like() {
+            Value::InlineTable(format_inline_table(item, &RELATION_SCHEMA_KEYS, comments)?)
+        } else {
+            let value = item
+                .as_value()
+                .with_context(|| format!("Unexpected value for '{key}'"))?;
+
+            if let Some(comment) = trailing_comment(value.decor()) {
+                comments.push_str(comment.trim());
+                comments.push('\n');
+            }
+
+            format_value(value)
+        };
+
+        formatted.insert(key, value);
+    }
+
+    formatted.fmt();
+
+    Ok(formatted)
+}
+
+/// Turns nested fields into an inline table with one inline table per field.
+fn format_inline_fields(item: &Item, comments: &mut String) -> Result<InlineTable> {
+    let fields = item
+        .as_table_like()
+        .with_context(|| "'fields' has to be a table")?;
+
+    if let Item::Table(table) = item {
+        comments.push_str(&decor_comments(Some(table.decor())));
+    }
+
+    let mut formatted = InlineTable::new();
+
+    for (field_name, field) in fields.iter() {
+        comments.push_str(&decor_comments(fields.key_decor(field_name)));
+
+        let value = format_inline_table(field, &FIELD_KEYS, comments)
+            .with_context(|| format!("Invalid field '{field_name}'"))?;
+        formatted.insert(field_name, Value::InlineTable(value));
+    }
+
+    formatted.fmt();
+
+    Ok(formatted)
+}
+
+/// Moves the comments of a table, written as standard or inline table, to a table header.
+fn header_decor(item: &Item, comments: String, is_first: bool) -> Decor {
+    let mut comments = comments;
+    let mut decor = Decor::default();
+
+    match item {
+        Item::Table(table) => {
+            comments.push_str(&comment_lines(table.decor().prefix()));
+            if let Some(comment) = trailing_comment(table.decor()) {
+                decor.set_suffix(comment);
+            }
+        }
+        Item::Value(value) => {
+            if let Some(comment) = trailing_comment(value.decor()) {
+                comments.push_str(comment.trim());
+                comments.push('\n');
+            }
+        }
+        _ => (),
+    }
+
+    // The first table of the file doesn't start with an empty line
+    if is_first {
+        decor.set_prefix(comments);
+    } else {
+        decor.set_prefix(format!("\n{comments}"));
+    }
+
+    decor
+}
+
+/// Formats the fields of a schema into a standard table with one inline table per field.
+fn format_fields(item: &Item, comments: String, is_first: bool) -> Result<Table> {
+    let fields = item
+        .as_table_like()
+        .with_context(|| "'fields' has to be a table")?;
+
+    let mut formatted = Table::new();
+    *formatted.decor_mut() = header_decor(item, comments, is_first);
+
+    for (field_name, field) in fields.iter() {
+        let mut field_comments = decor_comments(fields.key_decor(field_name));
+
+        let mut value = Value::InlineTable(
+            format_inline_table(field, &FIELD_KEYS, &mut field_comments)
+                .with_context(|| format!("Invalid field '{field_name}'"))?,
+        );
+
+        if let Some(comment) = field
+            .as_value()
+            .and_then(|value| trailing_comment(value.decor()))
+        {
+            value.decor_mut().set_suffix(comment);
+        }
+
+        formatted.insert(field_name, Item::Value(value));
+        if let Some(decor) = formatted.key_decor_mut(field_name) {
+            decor.set_prefix(field_comments);
+        }
+    }
+
+    Ok(formatted)
+}
+
+/// Formats a schema into a standard table, followed by the table of its fields.
+fn format_schema(item: &Item, comments: String, position: usize, is_first: bool) -> Result<Table> {
+    let schema = item.as_table_like().with_context(|| "Has to be a table")?;
+
+    let mut formatted = Table::new();
+    *formatted.decor_mut() = header_decor(item, comments, is_first);
+    formatted.set_position(position);
+
+    for (key, item) in ordered_entries(schema, &SCHEMA_KEYS) {
+        let key_comments = decor_comments(schema.key_decor(key));
+
+        if key == "fields" {
+            let mut fields = format_fields(item, key_comments, false)?;
+            fields.set_position(position + 1);
+            formatted.insert(key, Item::Table(fields));
+            continue;
+        }
+
+        let item = match item.as_value() {
+            Some(value) => Item::Value(format_value(value)),
+            None => item.clone(),
+        };
+
+        formatted.insert(key, item);
+        if let Some(decor) = formatted.key_decor_mut(key) {
+            decor.set_prefix(key_comments);
+        }
+    }
+
+    Ok(formatted)
+}
+
+/// Rewrites a TOML schema file into the canonical layout, keeping all comments.
+///
+/// Every schema is written as a table with its description, followed by a table of its fields,
+/// where every field is an inline table. Schemas and fields keep their order.
+pub fn format_schema_toml(content: &str) -> Result<String> {
+    let document: Document = content.parse()?;
+    let root = document.as_table();
+
+    let mut formatted = Document::new();
+    let mut position = 0;
+
+    for (key, item) in root.iter() {
+        let comments = decor_comments(root.key_decor(key));
+
+        if key == INCLUDE_KEY {
+            let value = item
+                .as_value()
+                .with_context(|| "'include' must be a list of paths")?;
+
+            let formatted_root = formatted.as_table_mut();
+            formatted_root.insert(key, Item::Value(format_value(value)));
+            if let Some(decor) = formatted_root.key_decor_mut(key) {
+                decor.set_prefix(comments);
+            }
+
+            continue;
+        }
+
+        let is_first = formatted.as_table().is_empty();
+
+        if key == MIXINS_KEY {
+            let mixins = item
+                .as_table_like()
+                .with_context(|| "'mixins' must be a table of fields by mixin name")?;
+
+            let mut formatted_mixins = Table::new();
+            formatted_mixins.set_implicit(true);
+
+            // Comments above the mixins belong to the first of them
+            let mut comments = comments;
+            for (index, (mixin_name, fields)) in mixins.iter().enumerate() {
+                let mixin_comments = comments + &decor_comments(mixins.key_decor(mixin_name));
+                comments = String::new();
+
+                let mut mixin = format_fields(fields, mixin_comments, is_first && index == 0)
+                    .with_context(|| format!("Invalid mixin '{mixin_name}'"))?;
+                mixin.set_position(position);
+                formatted_mixins.insert(mixin_name, Item::Table(mixin));
+
+                position += 1;
+            }
+
+            formatted
+                .as_table_mut()
+                .insert(key, Item::Table(formatted_mixins));
+            continue;
+        }
+
+        let schema = format_schema(item, comments, position, is_first)
+            .with_context(|| format!("Invalid schema '{key}'"))?;
+        formatted.as_table_mut().insert(key, Item::Table(schema));
+
+        position += 2;
+    }
+
+    let trailing = comment_lines(Some(document.trailing()));
+    if !trailing.is_empty() {
+        formatted.set_trailing(format!("\n{trailing}"));
+    }
+
+    Ok(formatted.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format_schema_toml;
+
+    #[test]
+    fn formats_schema_file() {
+        let content = r#"# Schemas of the blog
+include=[ "users.toml" ]
+
+[post]
+# Shown in the list of posts
+description='Blog post'
+
+[post.fields.title]
+type = "str"
+
+[post.fields.author]
+# Who wrote it
+schema = { name = "author" }
+type = "relation"
+
+[comment] # Not used yet
+fields.body.type = "str"
+description = "Comment"
+fields.post = { schema.name = "post", type = "relation" } # Relation
+"#;
+
+        let expected = r#"# Schemas of the blog
+include = ["users.toml"]
+
+[post]
+# Shown in the list of posts
+description = "Blog post"
+
+[post.fields]
+title = { type = "str" }
+# Who wrote it
+author = { type = "relation", schema = { name = "author" } }
+
+[comment] # Not used yet
+description = "Comment"
+
+[comment.fields]
+body = { type = "str" }
+post = { type = "relation", schema = { name = "post" } } # Relation
+"#;
+
+        let formatted = format_schema_toml(content).unwrap();
+        assert_eq!(formatted, expected);
+        assert_eq!(format_schema_toml(&formatted).unwrap(), formatted);
+    }
+
+    #[test]
+    fn formats_mixins() {
+        let content = r#"# Shared fields
+mixins.timestamps.created_at.type = "int"
+
+[post]
+fields = { title = { type = "str" } }
+extends = [ "timestamps" ]
+description = "Blog post"
+"#;
+
+        let expected = r#"# Shared fields
+[mixins.timestamps]
+created_at = { type = "int" }
+
+[post]
+description = "Blog post"
+extends = ["timestamps"]
+
+[post.fields]
+title = { type = "str" }
+"#;
+
+        assert_eq!(format_schema_toml(content).unwrap(), expected);
+    }
+}
@@ -0,0 +1,662 @@
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use anyhow::{anyhow, bail, Context as ErrorContext, Result};
+use argon2::{Algorithm, Argon2, Params, Version};
+use bip39::Mnemonic;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use p2panda_rs::identity::KeyPair;
+
+use crate::files::{EncryptedKeyFile, KdfParams};
+
+/// Length of a hex encoded ed25519 private key.
+const PRIVATE_KEY_LENGTH: usize = 64;
+
+/// Version of the encrypted key file format.
+const ENCRYPTED_KEY_VERSION: u64 = 1;
+
+/// Comment at the start of every encrypted key file.
+const ENCRYPTED_KEY_HEADER: &str = "# This file contains a private key encrypted by fishy.";
+
+/// Environment variable holding the private key, used when no other key source is given.
+pub const PRIVATE_KEY_ENV: &str = "FISHY_PRIVATE_KEY";
+
+/// Environment variable holding the passphrase of an encrypted key file.
+pub const PASSPHRASE_ENV: &str = "FISHY_PASSPHRASE";
+
+/// Environment variable holding the path to a file with the passphrase of an encrypted key file.
+pub const PASSPHRASE_FILE_ENV: &str = "FISHY_PASSPHRASE_FILE";
+
+/// Parses a hex encoded private key, ignoring surrounding whitespace.
+pub fn parse_private_key(value: &str) -> Result<KeyPair> {
+    let value = value.trim();
+
+    if value.len() != PRIVATE_KEY_LENGTH {
+        bail!(
+            "Expected a hex encoded private key with {PRIVATE_KEY_LENGTH} characters, found {}",
+            value.len()
+        );
+    }
+
+    if hex::decode(value).is_err() {
+        bail!("Private key contains characters which are not hexadecimal");
+    }
+
+    let key_pair = KeyPair::from_private_key_str(value)?;
+    Ok(key_pair)
+}
+
+/// Encodes the private key as a BIP39 mnemonic phrase of 24 words, including a checksum.
+pub fn key_pair_to_mnemonic(key_pair: &KeyPair) -> Result<String> {
+    let mnemonic = Mnemonic::from_entropy(key_pair.private_key().as_ref())
+        .map_err(|err| anyhow!("Could not encode private key as mnemonic: {err}"))?;
+
+    Ok(mnemonic.to_string())
+}
+
+/// Restores the private key from a BIP39 mnemonic phrase, verifying its checksum.
+pub fn mnemonic_to_key_pair(phrase: &str) -> Result<KeyPair> {
+    let phrase = phrase.split_whitespace().collect::<Vec<&str>>().join(" ");
+
+    let mnemonic = Mnemonic::parse_normalized(&phrase.to_lowercase())
+        .map_err(|err| anyhow!("Invalid mnemonic phrase: {err}"))?;
+
+    let entropy = mnemonic.to_entropy();
+    if entropy.len() * 2 != PRIVATE_KEY_LENGTH {
+        bail!("Mnemonic phrase does not contain a private key, expected 24 words");
+    }
+
+    parse_private_key(&hex::encode(entropy))
+}
+
+/// Derives the symmetric encryption key from a passphrase.
+fn derive_key(passphrase: &str, kdf: &KdfParams) -> Result<[u8; 32]> {
+    if kdf.algorithm != "argon2id" {
+        bail!("Unsupported key derivation function '{}'", kdf.algorithm);
+    }
+
+    let params = Params::new(kdf.m_cost, kdf.t_cost, kdf.p_cost, Some(32))
+        .map_err(|err| anyhow!("Invalid key derivation parameters: {err}"))?;
+    let salt = hex::decode(&kdf.salt).with_context(|| "Invalid salt in key file")?;
+
+    let mut key = [0u8; 32];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+        .map_err(|err| anyhow!("Could not derive key from passphrase: {err}"))?;
+
+    Ok(key)
+}
+
+/// Encrypts the private key with a key derived from the passphrase.
+pub fn encrypt_key_pair(key_pair: &KeyPair, passphrase: &str) -> Result<EncryptedKeyFile> {
+    let mut salt = [0u8; 16];
+    OsRng.fill_bytes(&mut salt);
+
+    let defaults = Params::default();
+    let kdf = KdfParams {
+        algorithm: "argon2id".into(),
+        salt: hex::encode(salt),
+        m_cost: defaults.m_cost(),
+        t_cost: defaults.t_cost(),
+        p_cost: defaults.p_cost(),
+    };
+
+    let key = derive_key(passphrase, &kdf)?;
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(&key));
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+
+    let ciphertext = cipher
+        .encrypt(&nonce, key_pair.private_key().as_ref())
+        .map_err(|_| anyhow!("Could not encrypt private key"))?;
+
+    Ok(EncryptedKeyFile {
+        version: ENCRYPTED_KEY_VERSION,
+        kdf,
+        nonce: hex::encode(nonce),
+        ciphertext: hex::encode(ciphertext),
+    })
+}
+
+/// Decrypts the private key with a key derived from the passphrase.
+pub fn decrypt_key_pair(key_file: &EncryptedKeyFile, passphrase: &str) -> Result<KeyPair> {
+    if key_file.version != ENCRYPTED_KEY_VERSION {
+        bail!(
+            "Unsupported encrypted key file version {}",
+            key_file.version
+        );
+    }
+
+    let key = derive_key(passphrase, &key_file.kdf)?;
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(&key));
+
+    let nonce = hex::decode(&key_file.nonce).with_context(|| "Invalid nonce in key file")?;
+    if nonce.len() != 12 {
+        bail!("Invalid nonce in key file");
+    }
+
+    let ciphertext =
+        hex::decode(&key_file.ciphertext).with_context(|| "Invalid ciphertext in key file")?;
+
+    let private_key = cipher
+        .decrypt(Nonce::from_slice(&nonce), ciphertext.as_ref())
+        .map_err(|_| anyhow!("Wrong passphrase or corrupted key file"))?;
+
+    parse_private_key(&hex::encode(private_key))
+}
+
+/// Reads the passphrase from the environment, a file or by prompting on the terminal.
+pub fn read_passphrase(confirm: bool) -> Result<String> {
+    passphrase_from(confirm, |name| env::var(name).ok())
+}
+
+/// Reads the passphrase, looking up environment variables with `env_var`.
+fn passphrase_from(confirm: bool, env_var: impl Fn(&str) -> Option<String>) -> Result<String> {
+    if let Some(passphrase) = env_var(PASSPHRASE_ENV) {
+        if passphrase.is_empty() {
+            bail!("Passphrase in {PASSPHRASE_ENV} can't be empty");
+        }
+
+        return Ok(passphrase);
+    }
+
+    if let Some(path) = env_var(PASSPHRASE_FILE_ENV) {
+        let passphrase = fs::read_to_string(&path)
+            .with_context(|| format!("Could not read passphrase file {path}"))?;
+        let passphrase = passphrase.trim_end_matches(['\r', '\n']);
+
+        if passphrase.is_empty() {
+            bail!("Passphrase file {path} is empty");
+        }
+
+        return Ok(passphrase.to_owned());
+    }
+
+    if !io::stdin().is_terminal() {
+        bail!("Passphrase required, set {PASSPHRASE_ENV} or {PASSPHRASE_FILE_ENV}");
+    }
+
+    let passphrase = rpassword::prompt_password("Passphrase: ")?;
+
+    if confirm {
+        if passphrase.is_empty() {
+            bail!("Passphrase can't be empty");
+        }
+
+        if rpassword::prompt_password("Repeat passphrase: ")? != passphrase {
+            bail!("Passphrases do not match");
+        }
+    }
+
+    Ok(passphrase)
+}
+
+/// Returns true if the content is an encrypted key file, even if it is corrupted or truncated.
+fn is_encrypted_content(content: &str) -> bool {
+    content.trim_start().starts_with(ENCRYPTED_KEY_HEADER) || content.contains("ciphertext")
+}
+
+/// Returns true if the key file is encrypted with a passphrase.
+pub fn is_encrypted_key_file(path: &Path) -> Result<bool> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Could not read key file {}", path.display()))?;
+
+    Ok(is_encrypted_content(&content))
+}
+
+/// Parses a plaintext or encrypted private key, asking for the passphrase if needed.
+fn parse_key_content(
+    content: &str,
+    passphrase: impl FnOnce() -> Result<String>,
+) -> Result<KeyPair> {
+    if !is_encrypted_content(content) {
+        return parse_private_key(content);
+    }
+
+    let key_file: EncryptedKeyFile = toml::from_str(content).map_err(|err| {
+        anyhow!(
+            "Could not decrypt key file, it is corrupted or truncated: {}",
+            err.message()
+        )
+    })?;
+
+    decrypt_key_pair(&key_file, &passphrase()?)
+}
+
+/// Paths under which the standard input can be opened as a file.
+const STDIN_PATHS: [&str; 3] = ["/dev/stdin", "/dev/fd/0", "/proc/self/fd/0"];
+
+/// Returns true if the passphrase is going to be read from stdin through a passphrase file.
+fn is_passphrase_from_stdin(env_var: impl Fn(&str) -> Option<String>) -> bool {
+    env_var(PASSPHRASE_ENV).is_none()
+        && env_var(PASSPHRASE_FILE_ENV).map_or(false, |path| STDIN_PATHS.contains(&path.as_str()))
+}
+
+/// Returns who besides the owner can access the key file, if anyone.
+#[cfg(unix)]
+fn accessible_by(path: &Path) -> Result<Option<&'static str>> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let metadata = fs::metadata(path)?;
+
+    // Pipes and terminals like `/dev/stdin` are no key files to restrict
+    if !metadata.is_file() {
+        return Ok(None);
+    }
+
+    let mode = metadata.permissions().mode();
+    let accessible_by = if mode & 0o007 != 0 {
+        Some("other users")
+    } else if mode & 0o070 != 0 {
+        Some("its group")
+    } else {
+        None
+    };
+
+    Ok(accessible_by)
+}
+
+/// Warns about key files which other users or the group can access and offers to restrict them.
+///
+/// Only asks when `can_prompt` is set, the answer would otherwise be taken from input meant for
+/// the key or passphrase.
+#[cfg(unix)]
+fn check_permissions(path: &Path, can_prompt: bool) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let accessible_by = match accessible_by(path)? {
+        Some(accessible_by) => accessible_by,
+        None => return Ok(()),
+    };
+    eprintln!(
+        "Warning: Key file {} is accessible by {accessible_by}",
+        path.display()
+    );
+
+    if !can_prompt {
+        eprintln!("Restrict it with `chmod 600 {}`", path.display());
+        return Ok(());
+    }
+
+    eprint!("Restrict it with `chmod 600 {}`? [y/N] ", path.display());
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+
+    if answer.trim().eq_ignore_ascii_case("y") {
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))
+            .with_context(|| format!("Could not restrict key file {}", path.display()))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_permissions(_path: &Path, _can_prompt: bool) -> Result<()> {
+    Ok(())
+}
+
+/// Returns true if the file is tracked by the git repository it is located in.
+fn is_tracked(path: &Path) -> bool {
+    let (directory, file_name) = match (path.parent(), path.file_name()) {
+        (Some(directory), Some(file_name)) => (directory, file_name),
+        _ => return false,
+    };
+
+    let directory = if directory.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        directory
+    };
+
+    // Ignore any errors, for example when git is not installed or this is not a repository
+    Command::new("git")
+        .arg("ls-files")
+        .arg("--error-unmatch")
+        .arg(file_name)
+        .current_dir(directory)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map(|status| status.success())
+        .unwrap_or(false)
+}
+
+/// Warns when the key file is tracked by the git repository it is located in.
+fn warn_if_tracked(path: &Path) {
+    if is_tracked(path) {
+        eprintln!(
+            "Warning: Key file {} is tracked by git, remove it with `git rm --cached {}` and \
+            consider the key compromised if it was ever pushed",
+            path.display(),
+            path.display()
+        );
+    }
+}
+
+/// Reads a key pair from a file, asking for the passphrase if it is encrypted.
+pub fn read_key_file(path: &Path) -> Result<KeyPair> {
+    let can_prompt =
+        io::stdin().is_terminal() && !is_passphrase_from_stdin(|name| env::var(name).ok());
+    check_permissions(path, can_prompt)?;
+    warn_if_tracked(path);
+
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Could not read key file {}", path.display()))?;
+
+    parse_key_content(&content, || read_passphrase(false))
+        .with_context(|| format!("Invalid key file {}", path.display()))
+}
+
+/// Where to read the private key for signing from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeySource {
+    /// Key file on disk.
+    File(PathBuf),
+
+    /// Environment variable `FISHY_PRIVATE_KEY`.
+    Env,
+
+    /// Standard input.
+    Stdin,
+
+    /// Already opened file descriptor, for example from a process substitution.
+    Fd(i32),
+}
+
+#[cfg(unix)]
+fn read_fd(fd: i32) -> Result<String> {
+    use std::mem::ManuallyDrop;
+    use std::os::unix::io::FromRawFd;
+
+    // Standard input, output and error can't be meant here and must not be consumed
+    if fd < 3 {
+        bail!("Invalid file descriptor {fd}, expected 3 or higher");
+    }
+
+    // Safety: The file descriptor was handed to us explicitly by the caller. We only borrow it,
+    // `ManuallyDrop` keeps it open after reading
+    let mut file = ManuallyDrop::new(unsafe { File::from_raw_fd(fd) });
+    let mut content = String::new();
+    file.read_to_string(&mut content)?;
+
+    Ok(content)
+}
+
+#[cfg(not(unix))]
+fn read_fd(_fd: i32) -> Result<String> {
+    bail!("Reading the private key from a file descriptor is only supported on unix systems")
+}
+
+/// Reads the key pair from the given source.
+///
+/// Errors never contain the key itself, only where it was read from.
+pub fn read_key(source: &KeySource) -> Result<KeyPair> {
+    match source {
+        KeySource::File(path) => read_key_file(path),
+        KeySource::Env => {
+            let content = env::var(PRIVATE_KEY_ENV)
+                .with_context(|| format!("Environment variable {PRIVATE_KEY_ENV} is not set"))?;
+            parse_key_content(&content, || read_passphrase(false))
+                .with_context(|| format!("Invalid private key in {PRIVATE_KEY_ENV}"))
+        }
+        KeySource::Stdin => {
+            let mut content = String::new();
+            io::stdin()
+                .read_to_string(&mut content)
+                .with_context(|| "Could not read private key from stdin")?;
+            parse_key_content(&content, || read_passphrase(false))
+                .with_context(|| "Invalid private key read from stdin")
+        }
+        KeySource::Fd(fd) => {
+            let content = read_fd(*fd)
+                .with_context(|| format!("Could not read private key from file descriptor {fd}"))?;
+            parse_key_content(&content, || read_passphrase(false))
+                .with_context(|| format!("Invalid private key read from file descriptor {fd}"))
+        }
+    }
+}
+
+/// Encodes the private key for storing it in a key file, optionally encrypted.
+pub fn key_file_content(key_pair: &KeyPair, encrypt: bool) -> Result<String> {
+    if !encrypt {
+        return Ok(hex::encode(key_pair.private_key()));
+    }
+
+    encrypted_key_file_content(key_pair, &read_passphrase(true)?)
+}
+
+fn encrypted_key_file_content(key_pair: &KeyPair, passphrase: &str) -> Result<String> {
+    let key_file = encrypt_key_pair(key_pair, passphrase)?;
+
+    Ok(format!(
+        "{}\n\n{}",
+        ENCRYPTED_KEY_HEADER,
+        toml::to_string_pretty(&key_file)?
+    ))
+}
+
+/// Writes the content of a key file, making it only readable and writable by its owner.
+pub fn store_key_file(path: &Path, content: &str, overwrite: bool) -> Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true);
+
+    if overwrite {
+        options.create(true).truncate(true);
+    } else {
+        options.create_new(true);
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+        options.mode(0o600);
+
+        // The mode is only applied when the file gets created, fix it for existing ones
+        if overwrite && path.exists() {
+            fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+        }
+    }
+
+    let mut file = options
+        .open(path)
+        .with_context(|| format!("Could not create key file {}", path.display()))?;
+    file.write_all(content.as_bytes())?;
+
+    Ok(())
+}
+
+/// Writes the private key into a new file, refusing to overwrite an existing one.
+pub fn write_key_file(path: &Path, key_pair: &KeyPair, encrypt: bool) -> Result<()> {
+    if path.exists() {
+        bail!("Found an already existing key file {}", path.display());
+    }
+
+    let content = key_file_content(key_pair, encrypt)?;
+    store_key_file(path, &content, false)
+}
+
+#[cfg(test)]
+mod tests {
+    use p2panda_rs::identity::KeyPair;
+
+    use super::{
+        decrypt_key_pair, encrypt_key_pair, encrypted_key_file_content, is_passphrase_from_stdin,
+        is_tracked, key_pair_to_mnemonic, mnemonic_to_key_pair, parse_key_content,
+        parse_private_key, passphrase_from, read_fd, store_key_file, PASSPHRASE_ENV,
+        PASSPHRASE_FILE_ENV,
+    };
+
+    #[test]
+    fn parses_private_keys() {
+        let key_pair = KeyPair::new();
+        let private_key_str = format!("{}\n", hex::encode(key_pair.private_key()));
+
+        let parsed = parse_private_key(&private_key_str).unwrap();
+        assert_eq!(parsed.public_key(), key_pair.public_key());
+
+        assert!(parse_private_key("abc").is_err());
+        assert!(parse_private_key(&"x".repeat(64)).is_err());
+    }
+
+    #[test]
+    fn encrypts_private_keys() {
+        let key_pair = KeyPair::new();
+        let key_file = encrypt_key_pair(&key_pair, "correct horse").unwrap();
+
+        let decrypted = decrypt_key_pair(&key_file, "correct horse").unwrap();
+        assert_eq!(decrypted.public_key(), key_pair.public_key());
+
+        assert!(decrypt_key_pair(&key_file, "wrong horse").is_err());
+    }
+
+    #[test]
+    fn detects_corrupted_key_files() {
+        let key_pair = KeyPair::new();
+        let content = encrypted_key_file_content(&key_pair, "correct horse").unwrap();
+        let passphrase = || Ok("correct horse".to_string());
+        assert_eq!(
+            parse_key_content(&content, passphrase)
+                .unwrap()
+                .public_key(),
+            key_pair.public_key()
+        );
+
+        let truncated = &content[..content.find("ciphertext").unwrap() + 20];
+        let err = parse_key_content(truncated, passphrase).unwrap_err();
+        assert!(err.to_string().contains("corrupted or truncated"));
+
+        let empty_env = |name: &str| (name == PASSPHRASE_ENV).then(String::new);
+        assert!(passphrase_from(false, empty_env).is_err());
+    }
+
+    #[test]
+    fn restores_private_keys_from_mnemonics() {
+        let key_pair = KeyPair::new();
+        let phrase = key_pair_to_mnemonic(&key_pair).unwrap();
+        assert_eq!(phrase.split(' ').count(), 24);
+
+        let restored = mnemonic_to_key_pair(&format!(" {}\n", phrase.to_uppercase())).unwrap();
+        assert_eq!(restored.public_key(), key_pair.public_key());
+
+        // Swapping two words breaks the checksum
+        let mut words: Vec<&str> = phrase.split(' ').collect();
+        words.swap(0, 1);
+        if words[0] != words[1] {
+            assert!(mnemonic_to_key_pair(&words.join(" ")).is_err());
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn reads_file_descriptors() {
+        use std::io::{Seek, Write};
+        use std::os::unix::io::AsRawFd;
+
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(b"secret").unwrap();
+        file.rewind().unwrap();
+
+        assert_eq!(read_fd(file.as_raw_fd()).unwrap(), "secret");
+
+        // The file descriptor is still open afterwards
+        file.rewind().unwrap();
+
+        assert!(read_fd(0).is_err());
+        assert!(read_fd(-1).is_err());
+    }
+
+    #[test]
+    fn reads_passphrase_from_stdin() {
+        let env_var = |passphrase: Option<&str>, path: Option<&str>| {
+            let passphrase = passphrase.map(String::from);
+            let path = path.map(String::from);
+            move |name: &str| match name {
+                PASSPHRASE_ENV => passphrase.clone(),
+                PASSPHRASE_FILE_ENV => path.clone(),
+                _ => None,
+            }
+        };
+
+        assert!(is_passphrase_from_stdin(env_var(None, Some("/dev/stdin"))));
+        assert!(!is_passphrase_from_stdin(env_var(
+            None,
+            Some("passphrase.txt")
+        )));
+        assert!(!is_passphrase_from_stdin(env_var(
+            Some("secret"),
+            Some("/dev/stdin")
+        )));
+        assert!(!is_passphrase_from_stdin(env_var(None, None)));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn detects_accessible_key_files() {
+        use std::fs;
+        use std::os::unix::fs::PermissionsExt;
+
+        use super::{accessible_by, check_permissions};
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("secret.txt");
+        let set_mode = |mode| fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+
+        store_key_file(&path, "key", false).unwrap();
+        assert_eq!(accessible_by(&path).unwrap(), None);
+
+        set_mode(0o644);
+        assert_eq!(accessible_by(&path).unwrap(), Some("other users"));
+
+        // Without a prompt the permissions are only reported
+        check_permissions(&path, false).unwrap();
+        assert_eq!(
+            fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o644
+        );
+
+        set_mode(0o640);
+        assert_eq!(accessible_by(&path).unwrap(), Some("its group"));
+
+        // Overwriting a key file restricts it again
+        store_key_file(&path, "key", true).unwrap();
+        assert_eq!(accessible_by(&path).unwrap(), None);
+
+        // Folders and other special files are no key files
+        assert_eq!(accessible_by(dir.path()).unwrap(), None);
+    }
+
+    #[test]
+    fn detects_tracked_key_files() {
+        use std::fs;
+        use std::process::Command;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("secret.txt");
+        fs::write(&path, "key").unwrap();
+
+        assert!(!is_tracked(&path));
+
+        let git = |args: &[&str]| {
+            Command::new("git")
+                .args(args)
+                .current_dir(dir.path())
+                .output()
+                .map(|output| output.status.success())
+                .unwrap_or(false)
+        };
+
+        // Skip the rest where git is not installed
+        if !git(&["init", "--quiet"]) {
+            return;
+        }
+        assert!(!is_tracked(&path));
+
+        assert!(git(&["add", "secret.txt"]));
+        assert!(is_tracked(&path));
+    }
+}
@@ -1,28 +1,59 @@
+mod check;
+mod client;
 mod commands;
 mod context;
 mod files;
+mod formatter;
+mod key;
+mod operations;
 mod schema;
+mod signer;
+mod store;
+mod workspace;
 
-use std::path::PathBuf;
+use std::env;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process;
 
 use anyhow::Result;
-use clap::{Parser, Subcommand};
-use commands::{init, publish, update};
+use clap::{Args, Parser, Subcommand};
+use commands::{
+    add_field, add_schema, check, codegen, convert, fmt, init, key_encrypt, key_export_mnemonic,
+    key_export_public, key_generate, key_import, key_restore_mnemonic, key_show, offline_export,
+    offline_import, offline_sign, publish, remove_field, remove_schema, serve, signer, status,
+    update, validate, workspace_check, workspace_fmt, workspace_publish, workspace_status,
+    workspace_update,
+};
+use p2panda_rs::schema::{SchemaId, SchemaName};
 use p2panda_rs::test_utils::memory_store::MemoryStore;
 
 use context::Context;
+use files::{NodeConfig, RelationId, RelationSchema, RelationSource, SchemaFormat};
+use key::{read_key, KeySource, PRIVATE_KEY_ENV};
+use signer::{ExternalSigner, SharedSigner, Signer};
+use store::ExternalSchemas;
+use workspace::read_workspace;
 
 /// A fictional versioning CLI.
 #[derive(Debug, Parser)]
 #[command(name = "fishy")]
 #[command(about = "Create, update or install p2panda schemas")]
 struct Cli {
-    #[arg(short, long = "schema", default_value = "schema.toml")]
-    schema_path: PathBuf,
+    /// Schema file [default: schema.toml, schema.yaml or schema.json, whichever exists]
+    #[arg(short, long = "schema", value_name = "PATH")]
+    schema_path: Option<PathBuf>,
+
+    /// Format of the schema file [default: detected by the file extension]
+    #[arg(long, value_enum)]
+    format: Option<SchemaFormat>,
 
     #[arg(short, long = "lock", default_value = "schema.lock")]
     lock_path: PathBuf,
 
+    #[arg(short, long = "config", default_value = "fishy.toml")]
+    config_path: PathBuf,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -34,37 +65,661 @@ enum Commands {
     Init {
         #[arg(default_value = "my_schema")]
         name: String,
+
+        /// Start from a template: "blog", "chat", "settings" or the path to your own schema file
+        /// or a folder containing one.
+        #[arg(short, long, value_name = "TEMPLATE")]
+        template: Option<String>,
+
+        /// Folder to initialise, created if it doesn't exist [default: current folder]
+        #[arg(short, long, value_name = "PATH")]
+        directory: Option<PathBuf>,
+
+        #[arg(short = 'k', long = "key", default_value = "secret.txt")]
+        private_key_path: PathBuf,
+
+        /// Encrypt the private key with a passphrase.
+        #[arg(long)]
+        encrypt: bool,
+
+        /// Overwrite an already existing schema and key file, the key is kept when a lock file
+        /// signed with it exists.
+        #[arg(long)]
+        force: bool,
     },
 
     /// Create or update schema.
     #[command()]
     Update {
-        #[arg(short = 'k', long = "key", default_value = "secret.txt")]
-        private_key_path: PathBuf,
+        #[command(flatten)]
+        key: KeyArgs,
+
+        /// Sign with an external signer instead of a local key, given as "unix:<path>" or
+        /// "exec:<command>".
+        #[arg(long, value_name = "URI", conflicts_with_all = ["private_key_path", "key_stdin", "key_fd"])]
+        signer: Option<String>,
+    },
+
+    /// Check the schema file for problems, without signing anything.
+    #[command()]
+    Check,
+
+    /// Convert the schema file into another format.
+    #[command()]
+    Convert {
+        /// File to write the converted schema to.
+        output: PathBuf,
+
+        /// Format to convert to [default: detected by the file extension]
+        #[arg(long, value_enum)]
+        to: Option<SchemaFormat>,
+
+        /// Overwrite an already existing output file.
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Check documents against the field types and local constraints of a schema.
+    ///
+    /// Exits with an error code if any document is invalid.
+    #[command()]
+    Validate {
+        /// Schema of the documents.
+        schema: SchemaName,
+
+        /// Files with the fields of one document each, in TOML, YAML or JSON.
+        #[arg(required = true)]
+        documents: Vec<PathBuf>,
+    },
+
+    /// Generate TypeScript types of the documents of all schemas.
+    #[command()]
+    Codegen {
+        /// File to write the types to.
+        output: PathBuf,
+
+        /// Overwrite an already existing output file.
+        #[arg(long)]
+        force: bool,
     },
 
+    /// Rewrite the schema file into the canonical layout, keeping all comments.
+    #[command()]
+    Fmt {
+        /// Only report files which are not formatted and exit with an error code if there are any.
+        #[arg(long)]
+        check: bool,
+    },
+
+    /// Show which changes of the schema are not in the lock file yet.
+    ///
+    /// Exits with an error code if there are pending changes.
+    #[command()]
+    Status,
+
     /// Deploy schema on a node.
     #[command()]
     Publish {
-        #[arg(short, long, default_value = "http://localhost:2020/graphql")]
-        endpoint: String,
+        #[command(flatten)]
+        node: NodeArgs,
+
+        /// Only publish the given schema and the schemas it depends on.
+        #[arg(long = "only", value_name = "NAME")]
+        schemas: Vec<SchemaName>,
+
+        /// Only report which commits are missing on the node, without publishing them.
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Run a local in-memory node with the schemas of the lock file.
+    #[command()]
+    Serve {
+        #[arg(short, long, default_value = "127.0.0.1:2020")]
+        address: SocketAddr,
+    },
+
+    /// Run a reference signer for the external signer protocol.
+    #[command()]
+    Signer {
+        #[command(flatten)]
+        key: KeyArgs,
+
+        /// Listen on this unix socket instead of stdin and stdout.
+        #[arg(long, value_name = "PATH")]
+        socket: Option<PathBuf>,
+    },
+
+    /// Add a schema or a field to the schema file.
+    #[command(subcommand)]
+    Add(AddCommands),
+
+    /// Remove a schema or a field from the schema file.
+    #[command(subcommand)]
+    Remove(RemoveCommands),
+
+    /// Manage private keys.
+    #[command(subcommand)]
+    Key(KeyCommands),
+
+    /// Sign schema updates on another, offline machine.
+    #[command(subcommand)]
+    Offline(OfflineCommands),
+}
+
+#[derive(Debug, Subcommand)]
+enum OfflineCommands {
+    /// Export the pending schema changes into a plan file, no key needed.
+    #[command()]
+    Export {
+        /// Public key of the author who will sign the plan [default: author of schema.lock]
+        #[arg(long, value_name = "KEY")]
+        public_key: Option<String>,
+
+        #[arg(short, long, default_value = "plan.toml")]
+        output: PathBuf,
+    },
+
+    /// Sign the operations of a plan file.
+    #[command()]
+    Sign {
+        #[arg(default_value = "plan.toml")]
+        plan: PathBuf,
+
+        #[command(flatten)]
+        key: KeyArgs,
+
+        /// Sign with an external signer instead of a local key, given as "unix:<path>" or
+        /// "exec:<command>".
+        #[arg(long, value_name = "URI", conflicts_with_all = ["private_key_path", "key_stdin", "key_fd"])]
+        signer: Option<String>,
+
+        /// File to write the signed plan to [default: overwrite the plan]
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+    },
+
+    /// Verify a signed plan file and add its commits to schema.lock.
+    #[command()]
+    Import {
+        #[arg(default_value = "plan.toml")]
+        plan: PathBuf,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum AddCommands {
+    /// Add a new schema with at least one field, relation fields are added with `add field`.
+    #[command()]
+    Schema {
+        name: SchemaName,
+
+        #[arg(short, long, default_value = "")]
+        description: String,
+
+        /// Field of the new schema, one of bool, float, int or str.
+        #[arg(long = "field", value_name = "NAME:TYPE", value_parser = parse_field, required = true)]
+        fields: Vec<(String, String)>,
+    },
+
+    /// Add a field to a schema.
+    #[command()]
+    Field {
+        schema: SchemaName,
+
+        name: String,
+
+        /// One of bool, float, int, str, relation, relation_list, pinned_relation or
+        /// pinned_relation_list.
+        #[arg(value_name = "TYPE")]
+        field_type: String,
+
+        #[command(flatten)]
+        relation: RelationArgs,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum RemoveCommands {
+    /// Remove a schema which isn't related to by other schemas.
+    #[command()]
+    Schema { name: SchemaName },
+
+    /// Remove a field from a schema.
+    #[command()]
+    Field { schema: SchemaName, name: String },
+}
+
+#[derive(Debug, Subcommand)]
+enum KeyCommands {
+    /// Generate a new private key.
+    #[command()]
+    Generate {
+        #[arg(short = 'k', long = "key", default_value = "secret.txt")]
+        private_key_path: PathBuf,
+
+        /// Encrypt the private key with a passphrase.
+        #[arg(long)]
+        encrypt: bool,
+    },
+
+    /// Print the public key of a private key file.
+    #[command()]
+    Show {
+        #[arg(short = 'k', long = "key", default_value = "secret.txt")]
+        private_key_path: PathBuf,
+    },
+
+    /// Import a hex encoded private key from a file or stdin.
+    #[command()]
+    Import {
+        #[arg(short = 'k', long = "key", default_value = "secret.txt")]
+        private_key_path: PathBuf,
+
+        /// File to read the private key from, otherwise it is read from stdin.
+        #[arg(long, value_name = "PATH")]
+        from: Option<PathBuf>,
+
+        /// Encrypt the private key with a passphrase.
+        #[arg(long)]
+        encrypt: bool,
+    },
+
+    /// Print the private key as a mnemonic phrase for a paper backup.
+    #[command()]
+    ExportMnemonic {
+        #[arg(short = 'k', long = "key", default_value = "secret.txt")]
+        private_key_path: PathBuf,
+    },
+
+    /// Restore a private key file from a mnemonic phrase read from stdin.
+    #[command()]
+    RestoreMnemonic {
+        #[arg(short = 'k', long = "key", default_value = "secret.txt")]
+        private_key_path: PathBuf,
+
+        /// Encrypt the private key with a passphrase.
+        #[arg(long)]
+        encrypt: bool,
+    },
+
+    /// Encrypt an existing plaintext private key file with a passphrase.
+    #[command()]
+    Encrypt {
+        #[arg(short = 'k', long = "key", default_value = "secret.txt")]
+        private_key_path: PathBuf,
+    },
+
+    /// Export only the public key to share it with others.
+    #[command()]
+    ExportPublic {
+        #[arg(short = 'k', long = "key", default_value = "secret.txt")]
+        private_key_path: PathBuf,
+
+        /// File to write the public key to, otherwise it is printed.
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
     },
 }
 
+/// Where to read the private key for signing from.
+///
+/// Precedence: `--key-stdin`, `--key-fd`, `--key`, the `FISHY_PRIVATE_KEY` environment variable
+/// and finally the default `secret.txt` file.
+#[derive(Debug, Args)]
+struct KeyArgs {
+    /// Path to the private key file [default: secret.txt]
+    #[arg(short = 'k', long = "key", value_name = "PATH")]
+    private_key_path: Option<PathBuf>,
+
+    /// Read the private key from stdin.
+    #[arg(long, conflicts_with_all = ["key_fd", "private_key_path"])]
+    key_stdin: bool,
+
+    /// Read the private key from an open file descriptor, 3 or higher.
+    #[arg(long, value_name = "FD", conflicts_with = "private_key_path")]
+    key_fd: Option<i32>,
+}
+
+impl KeyArgs {
+    /// Returns true if a key source other than the default key file was chosen.
+    fn is_given(&self) -> bool {
+        self.key_stdin
+            || self.key_fd.is_some()
+            || self.private_key_path.is_some()
+            || env::var_os(PRIVATE_KEY_ENV).is_some()
+    }
+}
+
+impl From<KeyArgs> for KeySource {
+    fn from(args: KeyArgs) -> Self {
+        if args.key_stdin {
+            KeySource::Stdin
+        } else if let Some(fd) = args.key_fd {
+            KeySource::Fd(fd)
+        } else if let Some(path) = args.private_key_path {
+            KeySource::File(path)
+        } else if env::var_os(PRIVATE_KEY_ENV).is_some() {
+            KeySource::Env
+        } else {
+            KeySource::File(PathBuf::from("secret.txt"))
+        }
+    }
+}
+
+/// Schema a relation field points to.
+#[derive(Debug, Args)]
+struct RelationArgs {
+    /// Name of the related schema.
+    #[arg(long, value_name = "SCHEMA", conflicts_with = "id")]
+    to: Option<SchemaName>,
+
+    /// Id of the related schema, for schemas which are not managed with fishy.
+    #[arg(long, value_name = "SCHEMA_ID")]
+    id: Option<SchemaId>,
+
+    /// Git repository defining the related schema.
+    #[arg(long, value_name = "URL", requires = "to", conflicts_with = "path")]
+    git: Option<String>,
+
+    /// Folder defining the related schema.
+    #[arg(long, value_name = "PATH", requires = "to")]
+    path: Option<String>,
+}
+
+impl RelationArgs {
+    fn relation_schema(self) -> Option<RelationSchema> {
+        let external = match (self.git, self.path) {
+            (Some(url), _) => Some(RelationSource::Git(url)),
+            (None, Some(path)) => Some(RelationSource::Path(path)),
+            (None, None) => None,
+        };
+
+        match (self.to, self.id) {
+            (Some(name), _) => Some(RelationSchema {
+                id: RelationId::Name(name),
+                external,
+            }),
+            (None, Some(id)) => Some(RelationSchema {
+                id: RelationId::Id(id),
+                external: None,
+            }),
+            (None, None) => None,
+        }
+    }
+}
+
+/// Options to connect to a node, overriding the ones from the config file.
+#[derive(Debug, Args)]
+struct NodeArgs {
+    /// GraphQL endpoint of the node [default: http://localhost:2020/graphql]
+    #[arg(short, long)]
+    endpoint: Option<String>,
+
+    /// Additional HTTP header sent with every request, formatted as "Name: value".
+    #[arg(long = "header", value_name = "HEADER", value_parser = parse_header)]
+    headers: Vec<(String, String)>,
+
+    /// Read a bearer token from this environment variable.
+    #[arg(long, value_name = "NAME")]
+    token_env: Option<String>,
+
+    /// Read a bearer token from this file.
+    #[arg(long, value_name = "PATH", conflicts_with = "token_env")]
+    token_file: Option<PathBuf>,
+
+    /// PEM encoded CA certificate bundle to verify the node with.
+    #[arg(long, value_name = "PATH")]
+    ca_cert: Option<PathBuf>,
+
+    /// PEM encoded client certificate for mutual TLS.
+    #[arg(long, value_name = "PATH", requires = "client_key")]
+    client_cert: Option<PathBuf>,
+
+    /// PEM encoded private key of the client certificate.
+    #[arg(long, value_name = "PATH", requires = "client_cert")]
+    client_key: Option<PathBuf>,
+
+    /// Request timeout in seconds.
+    #[arg(long, value_name = "SECONDS")]
+    timeout: Option<u64>,
+}
+
+impl From<NodeArgs> for NodeConfig {
+    fn from(args: NodeArgs) -> Self {
+        Self {
+            endpoint: args.endpoint,
+            headers: args.headers.into_iter().collect(),
+            token_env: args.token_env,
+            token_file: args.token_file,
+            ca_cert: args.ca_cert,
+            client_cert: args.client_cert,
+            client_key: args.client_key,
+            timeout: args.timeout,
+        }
+    }
+}
+
+/// Uses the external signer if one is given, otherwise the private key.
+fn build_signer(key: KeyArgs, signer: Option<String>) -> Result<Box<dyn Signer>> {
+    match signer {
+        Some(uri) => Ok(Box::new(ExternalSigner::connect(&uri)?)),
+        None => Ok(Box::new(read_key(&key.into())?)),
+    }
+}
+
+/// Path of the schema file if none was given, preferring existing files in the given folder.
+fn default_schema_path(format: Option<SchemaFormat>, directory: &Path) -> PathBuf {
+    if let Some(format) = format {
+        return PathBuf::from(format!("schema.{}", format.extension()));
+    }
+
+    SchemaFormat::EXTENSIONS
+        .iter()
+        .map(|extension| PathBuf::from(format!("schema.{extension}")))
+        .find(|path| directory.join(path).exists())
+        .unwrap_or_else(|| PathBuf::from("schema.toml"))
+}
+
+fn parse_header(value: &str) -> Result<(String, String), String> {
+    match value.split_once(':') {
+        Some((name, value)) => Ok((name.trim().to_string(), value.trim().to_string())),
+        None => Err("expected header formatted as \"Name: value\"".into()),
+    }
+}
+
+fn parse_field(value: &str) -> Result<(String, String), String> {
+    match value.split_once(':') {
+        Some((name, field_type)) => Ok((name.trim().to_string(), field_type.trim().to_string())),
+        None => Err("expected field formatted as \"name:type\"".into()),
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Cli::parse();
     let store = MemoryStore::default();
-    let context = Context::new(store, &args.schema_path, &args.lock_path);
+
+    // `init` looks for an existing schema file in its target folder
+    let directory = match &args.command {
+        Commands::Init {
+            directory: Some(directory),
+            ..
+        } => directory.as_path(),
+        _ => Path::new(""),
+    };
+    let schema_path = args
+        .schema_path
+        .unwrap_or_else(|| default_schema_path(args.format, directory));
+    let schema_format = args
+        .format
+        .unwrap_or_else(|| SchemaFormat::from_path(&schema_path));
+
+    let context = Context::new(
+        store,
+        &schema_path,
+        &args.lock_path,
+        &args.config_path,
+        schema_format,
+    );
 
     match args.command {
-        Commands::Init { name } => {
-            init(context, &name)?;
+        Commands::Init {
+            name,
+            template,
+            directory,
+            private_key_path,
+            encrypt,
+            force,
+        } => {
+            init(
+                context,
+                directory.as_deref(),
+                &private_key_path,
+                &name,
+                template.as_deref(),
+                encrypt,
+                force,
+            )
+            .await?;
+        }
+        Commands::Update { key, signer } => match read_workspace(&context)? {
+            Some(members) => {
+                // Relative key files are resolved inside of every member, like the schema and
+                // lock files, other key sources are shared by all members
+                let is_shared = signer.is_some()
+                    || match &key.private_key_path {
+                        Some(path) => path.is_absolute(),
+                        None => key.is_given(),
+                    };
+                let member_key_path = key
+                    .private_key_path
+                    .clone()
+                    .unwrap_or_else(|| PathBuf::from("secret.txt"));
+
+                let shared_signer = if is_shared {
+                    Some(SharedSigner::new(build_signer(key, signer)?))
+                } else {
+                    None
+                };
+
+                workspace_update(context, &members, |member| match &shared_signer {
+                    Some(shared_signer) => Ok(Box::new(shared_signer.clone())),
+                    None => Ok(Box::new(read_key(&KeySource::File(
+                        member.path.join(&member_key_path),
+                    ))?)),
+                })
+                .await?;
+            }
+            None => {
+                update(context, build_signer(key, signer)?, &ExternalSchemas::new()).await?;
+            }
+        },
+        Commands::Check => match read_workspace(&context)? {
+            Some(members) => workspace_check(&members)?,
+            None => check(context, &[])?,
+        },
+        Commands::Convert { output, to, force } => convert(context, &output, to, force)?,
+        Commands::Validate { schema, documents } => {
+            if !validate(context, &schema, &documents)? {
+                process::exit(1);
+            }
         }
-        Commands::Update { private_key_path } => {
-            update(context, &private_key_path).await?;
+        Commands::Codegen { output, force } => codegen(context, &output, force)?,
+        Commands::Fmt { check } => {
+            let is_formatted = match read_workspace(&context)? {
+                Some(members) => workspace_fmt(context, &members, check)?,
+                None => fmt(context, check)?,
+            };
+
+            if check && !is_formatted {
+                process::exit(1);
+            }
+        }
+        Commands::Status => {
+            let is_up_to_date = match read_workspace(&context)? {
+                Some(members) => workspace_status(context, &members).await?,
+                None => status(context, &ExternalSchemas::new()).await?,
+            };
+
+            if !is_up_to_date {
+                process::exit(1);
+            }
         }
-        Commands::Publish { endpoint } => publish(context, &endpoint).await?,
+        Commands::Publish {
+            node,
+            schemas,
+            dry_run,
+        } => match read_workspace(&context)? {
+            Some(members) => {
+                workspace_publish(context, &members, node.into(), &schemas, dry_run).await?
+            }
+            None => publish(context, node.into(), &schemas, dry_run).await?,
+        },
+        Commands::Serve { address } => serve(context, &address).await?,
+        Commands::Signer { key, socket } => signer(&key.into(), socket.as_deref())?,
+        Commands::Add(command) => match command {
+            AddCommands::Schema {
+                name,
+                description,
+                fields,
+            } => add_schema(context, &name, &description, &fields)?,
+            AddCommands::Field {
+                schema,
+                name,
+                field_type,
+                relation,
+            } => add_field(
+                context,
+                &schema,
+                &name,
+                &field_type,
+                relation.relation_schema(),
+            )?,
+        },
+        Commands::Remove(command) => match command {
+            RemoveCommands::Schema { name } => remove_schema(context, &name)?,
+            RemoveCommands::Field { schema, name } => remove_field(context, &schema, &name)?,
+        },
+        Commands::Offline(command) => match command {
+            OfflineCommands::Export { public_key, output } => {
+                offline_export(context, public_key.as_deref(), &output).await?
+            }
+            OfflineCommands::Sign {
+                plan,
+                key,
+                signer,
+                output,
+            } => {
+                let output = output.unwrap_or(plan.clone());
+                offline_sign(context, &plan, build_signer(key, signer)?, &output).await?
+            }
+            OfflineCommands::Import { plan } => offline_import(context, &plan).await?,
+        },
+        Commands::Key(command) => match command {
+            KeyCommands::Generate {
+                private_key_path,
+                encrypt,
+            } => key_generate(&private_key_path, encrypt)?,
+            KeyCommands::Show { private_key_path } => key_show(&private_key_path)?,
+            KeyCommands::Import {
+                private_key_path,
+                from,
+                encrypt,
+            } => key_import(from.as_deref(), &private_key_path, encrypt)?,
+            KeyCommands::ExportMnemonic { private_key_path } => {
+                key_export_mnemonic(&private_key_path)?
+            }
+            KeyCommands::RestoreMnemonic {
+                private_key_path,
+                encrypt,
+            } => key_restore_mnemonic(&private_key_path, encrypt)?,
+            KeyCommands::Encrypt { private_key_path } => key_encrypt(&private_key_path)?,
+            KeyCommands::ExportPublic {
+                private_key_path,
+                output,
+            } => key_export_public(&private_key_path, output.as_deref())?,
+        },
     };
 
     Ok(())
@@ -0,0 +1,90 @@
+use anyhow::{bail, Result};
+use p2panda_rs::operation::decode::decode_operation;
+use p2panda_rs::operation::plain::PlainValue;
+use p2panda_rs::operation::traits::{Actionable, Schematic};
+use p2panda_rs::operation::OperationAction;
+use p2panda_rs::schema::SchemaId;
+
+use crate::context::Context;
+use crate::files::{field_origins, read_schema_definitions, Commit, PlannedOperation};
+
+fn describe_commit(commit: &Commit) -> Result<PlannedOperation> {
+    let operation = decode_operation(&commit.operation)?;
+
+    let kind = match operation.schema_id() {
+        SchemaId::SchemaDefinition(_) => "schema",
+        SchemaId::SchemaFieldDefinition(_) => "field",
+        value => bail!(
+            "Unexpected schema id '{value}' in commit {}",
+            commit.entry_hash
+        ),
+    };
+
+    let action = match operation.action() {
+        OperationAction::Create => "create",
+        OperationAction::Update => "update",
+        OperationAction::Delete => "delete",
+    };
+
+    let name = match operation
+        .fields()
+        .as_ref()
+        .and_then(|fields| fields.get("name"))
+    {
+        Some(PlainValue::StringOrRelation(name)) => name.to_owned(),
+        _ => String::new(),
+    };
+
+    Ok(PlannedOperation {
+        action: action.into(),
+        kind: kind.into(),
+        name,
+    })
+}
+
+/// Describes what each commit does, in the order of the given commits.
+pub fn describe_commits(commits: &[Commit]) -> Result<Vec<PlannedOperation>> {
+    commits.iter().map(describe_commit).collect()
+}
+
+pub fn print_operations(operations: &[PlannedOperation]) {
+    for operation in operations {
+        println!("  {operation}");
+    }
+}
+
+/// Prints the fields of the planned schemas if some of them are inherited from mixins or other
+/// schemas, together with where each field comes from.
+pub fn print_inherited_fields(context: &Context, operations: &[PlannedOperation]) -> Result<()> {
+    let (mixins, schema_file) =
+        read_schema_definitions(&context.schema_path, context.schema_format)?;
+    let origins = field_origins(&schema_file, &mixins)?;
+
+    for operation in operations
+        .iter()
+        .filter(|operation| operation.kind == "schema" && operation.action != "delete")
+    {
+        let fields = match origins
+            .iter()
+            .find(|(schema_name, _)| schema_name.to_string() == operation.name)
+        {
+            Some((_, fields)) => fields,
+            None => continue,
+        };
+
+        if fields.values().all(|origin| *origin == operation.name) {
+            continue;
+        }
+
+        println!("Fields of {}:", operation.name);
+        for (field_name, origin) in fields {
+            if *origin == operation.name {
+                println!("  {field_name}");
+            } else {
+                println!("  {field_name} (from {origin})");
+            }
+        }
+    }
+
+    Ok(())
+}
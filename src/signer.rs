@@ -0,0 +1,386 @@
+//! Signing of entries, either with a local key pair or by an external signer.
+//!
+//! External signers speak a simple line based protocol, every request and response is one line
+//! of UTF-8 text:
+//!
+//! ```text
+//! > PUBLIC_KEY
+//! < OK <hex encoded public key>
+//! > SIGN <hex encoded bytes>
+//! < OK <hex encoded ed25519 signature>
+//! ```
+//!
+//! Errors are answered with `ERR <message>`. The signer is either reached over a unix socket
+//! (`unix:<path>`) or started as a subprocess talking over stdin and stdout (`exec:<command>`).
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, bail, Context as ErrorContext, Result};
+use p2panda_rs::entry::decode::decode_entry;
+use p2panda_rs::entry::{EncodedEntry, LogId, SeqNum};
+use p2panda_rs::hash::Hash;
+use p2panda_rs::identity::{KeyPair, PublicKey};
+use p2panda_rs::operation::EncodedOperation;
+
+/// Length of an ed25519 signature in bytes.
+const SIGNATURE_LENGTH: usize = 64;
+
+/// Something which can sign bytes on behalf of an author.
+pub trait Signer: Send {
+    /// Public key of the author the signatures are created for.
+    fn public_key(&mut self) -> Result<PublicKey>;
+
+    /// Returns the ed25519 signature of the given bytes.
+    fn sign(&mut self, bytes: &[u8]) -> Result<Vec<u8>>;
+}
+
+impl Signer for KeyPair {
+    fn public_key(&mut self) -> Result<PublicKey> {
+        Ok(KeyPair::public_key(self))
+    }
+
+    fn sign(&mut self, bytes: &[u8]) -> Result<Vec<u8>> {
+        Ok(KeyPair::sign(self, bytes).to_bytes().to_vec())
+    }
+}
+
+/// Signer which can be handed out several times, for example to update many folders with the
+/// same key.
+#[derive(Clone)]
+pub struct SharedSigner(Arc<Mutex<Box<dyn Signer>>>);
+
+impl SharedSigner {
+    pub fn new(signer: Box<dyn Signer>) -> Self {
+        Self(Arc::new(Mutex::new(signer)))
+    }
+}
+
+impl Signer for SharedSigner {
+    fn public_key(&mut self) -> Result<PublicKey> {
+        self.0.lock().unwrap().public_key()
+    }
+
+    fn sign(&mut self, bytes: &[u8]) -> Result<Vec<u8>> {
+        self.0.lock().unwrap().sign(bytes)
+    }
+}
+
+/// Signer running in another process, reached over a unix socket or stdin and stdout.
+pub struct ExternalSigner {
+    reader: Box<dyn BufRead + Send>,
+    writer: Box<dyn Write + Send>,
+    child: Option<Child>,
+}
+
+impl ExternalSigner {
+    /// Connects to an external signer, given as `unix:<path>` or `exec:<command>`.
+    pub fn connect(uri: &str) -> Result<Self> {
+        if let Some(path) = uri.strip_prefix("unix:") {
+            return Self::connect_socket(path);
+        }
+
+        if let Some(command) = uri.strip_prefix("exec:") {
+            return Self::spawn(command);
+        }
+
+        bail!("Unknown signer '{uri}', expected 'unix:<path>' or 'exec:<command>'")
+    }
+
+    #[cfg(unix)]
+    fn connect_socket(path: &str) -> Result<Self> {
+        use std::os::unix::net::UnixStream;
+
+        let stream = UnixStream::connect(path)
+            .with_context(|| format!("Could not connect to signer at {path}"))?;
+
+        Ok(Self {
+            reader: Box::new(BufReader::new(stream.try_clone()?)),
+            writer: Box::new(stream),
+            child: None,
+        })
+    }
+
+    #[cfg(not(unix))]
+    fn connect_socket(_path: &str) -> Result<Self> {
+        bail!("Unix socket signers are only supported on unix systems")
+    }
+
+    fn spawn(command: &str) -> Result<Self> {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Could not start signer '{command}'"))?;
+
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+
+        Ok(Self {
+            reader: Box::new(BufReader::new(stdout)),
+            writer: Box::new(stdin),
+            child: Some(child),
+        })
+    }
+
+    fn request(&mut self, request: &str) -> Result<String> {
+        writeln!(self.writer, "{request}")?;
+        self.writer.flush()?;
+
+        let mut response = String::new();
+        if self.reader.read_line(&mut response)? == 0 {
+            bail!("Signer closed the connection");
+        }
+
+        match response.trim_end().split_once(' ') {
+            Some(("OK", value)) => Ok(value.to_owned()),
+            Some(("ERR", message)) => bail!("Signer failed: {message}"),
+            _ => bail!("Invalid response from signer"),
+        }
+    }
+}
+
+impl Signer for ExternalSigner {
+    fn public_key(&mut self) -> Result<PublicKey> {
+        let public_key = self.request("PUBLIC_KEY")?;
+        PublicKey::new(&public_key).map_err(|err| anyhow!("Invalid public key from signer: {err}"))
+    }
+
+    fn sign(&mut self, bytes: &[u8]) -> Result<Vec<u8>> {
+        let signature = hex::decode(self.request(&format!("SIGN {}", hex::encode(bytes)))?)
+            .with_context(|| "Invalid signature from signer")?;
+
+        if signature.len() != SIGNATURE_LENGTH {
+            bail!("Invalid signature length from signer");
+        }
+
+        Ok(signature)
+    }
+}
+
+impl Drop for ExternalSigner {
+    fn drop(&mut self) {
+        if let Some(child) = self.child.as_mut() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+/// Answers signing requests of one connection with the given key pair until it is closed.
+///
+/// This is the reference implementation of the signer protocol.
+pub fn serve_signer(key_pair: &KeyPair, reader: impl Read, mut writer: impl Write) -> Result<()> {
+    let mut reader = BufReader::new(reader);
+    let mut line = String::new();
+
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+
+        let response = match line.trim_end().split_once(' ') {
+            None if line.trim_end() == "PUBLIC_KEY" => format!("OK {}", key_pair.public_key()),
+            Some(("SIGN", bytes)) => match hex::decode(bytes) {
+                Ok(bytes) => format!("OK {}", hex::encode(key_pair.sign(&bytes).to_bytes())),
+                Err(_) => "ERR Invalid hex encoding".to_string(),
+            },
+            _ => "ERR Unknown request".to_string(),
+        };
+
+        writeln!(writer, "{response}")?;
+        writer.flush()?;
+    }
+}
+
+/// Appends a number in the variable length `varu64` encoding used by bamboo.
+fn encode_varu64(value: u64, bytes: &mut Vec<u8>) {
+    if value < 248 {
+        bytes.push(value as u8);
+        return;
+    }
+
+    let be_bytes = value.to_be_bytes();
+    let skip = be_bytes.iter().take_while(|byte| **byte == 0).count();
+    bytes.push(247 + (8 - skip) as u8);
+    bytes.extend_from_slice(&be_bytes[skip..]);
+}
+
+fn encode_hash(hash: &Hash, bytes: &mut Vec<u8>) -> Result<()> {
+    bytes.extend(hex::decode(hash.as_str())?);
+    Ok(())
+}
+
+/// Creates a signed entry for the operation, using the arguments given by `next_args`.
+///
+/// The entry is encoded in the bamboo format without signature, signed and then decoded again to
+/// make sure the signer produced a valid signature.
+pub fn sign_entry(
+    signer: &mut dyn Signer,
+    public_key: &PublicKey,
+    log_id: &LogId,
+    seq_num: &SeqNum,
+    skiplink: Option<&Hash>,
+    backlink: Option<&Hash>,
+    operation: &EncodedOperation,
+) -> Result<EncodedEntry> {
+    let payload = hex::decode(operation.to_string())?;
+
+    let mut bytes = vec![0];
+    bytes.extend(hex::decode(public_key.to_string())?);
+    encode_varu64(log_id.as_u64(), &mut bytes);
+    encode_varu64(seq_num.as_u64(), &mut bytes);
+
+    if let Some(skiplink) = skiplink {
+        encode_hash(skiplink, &mut bytes)?;
+    }
+
+    if let Some(backlink) = backlink {
+        encode_hash(backlink, &mut bytes)?;
+    }
+
+    encode_varu64(payload.len() as u64, &mut bytes);
+    encode_hash(&Hash::new_from_bytes(&payload), &mut bytes)?;
+
+    let signature = signer.sign(&bytes)?;
+    bytes.extend(signature);
+
+    let encoded_entry = EncodedEntry::from_bytes(&bytes);
+    decode_entry(&encoded_entry).map_err(|err| anyhow!("Signer created invalid entry: {err}"))?;
+
+    Ok(encoded_entry)
+}
+
+#[cfg(test)]
+mod tests {
+    use p2panda_rs::entry::encode::sign_and_encode_entry;
+    use p2panda_rs::entry::{LogId, SeqNum};
+    use p2panda_rs::hash::Hash;
+    use p2panda_rs::identity::KeyPair;
+    use p2panda_rs::operation::encode::encode_operation;
+    use p2panda_rs::operation::{EncodedOperation, OperationBuilder};
+    use p2panda_rs::schema::SchemaId;
+
+    use super::{encode_varu64, serve_signer, sign_entry, ExternalSigner, Signer};
+
+    fn operation() -> EncodedOperation {
+        let operation = OperationBuilder::new(&SchemaId::SchemaFieldDefinition(1))
+            .fields(&[("name", "title".into()), ("type", "str".into())])
+            .build()
+            .unwrap();
+
+        encode_operation(&operation).unwrap()
+    }
+
+    #[test]
+    fn varu64() {
+        let encode = |value| {
+            let mut bytes = Vec::new();
+            encode_varu64(value, &mut bytes);
+            bytes
+        };
+
+        assert_eq!(encode(0), vec![0]);
+        assert_eq!(encode(247), vec![247]);
+        assert_eq!(encode(248), vec![248, 248]);
+        assert_eq!(encode(256), vec![249, 1, 0]);
+        assert_eq!(
+            encode(u64::MAX),
+            vec![255, 255, 255, 255, 255, 255, 255, 255, 255]
+        );
+    }
+
+    #[test]
+    fn signs_entries_like_p2panda() {
+        let mut key_pair = KeyPair::new();
+        let public_key = key_pair.public_key();
+        let operation = operation();
+
+        let backlink = Hash::new_from_bytes(&[1, 2, 3]);
+        let skiplink = Hash::new_from_bytes(&[4, 5, 6]);
+
+        // The entry at seq num 4 needs a skiplink, the one at seq num 2 doesn't
+        let links = [
+            (1, None, None),
+            (2, None, Some(&backlink)),
+            (4, Some(&skiplink), Some(&backlink)),
+        ];
+
+        for (seq_num, skiplink, backlink) in links {
+            let log_id = LogId::new(3);
+            let seq_num = SeqNum::new(seq_num).unwrap();
+
+            let expected =
+                sign_and_encode_entry(&log_id, &seq_num, skiplink, backlink, &operation, &key_pair)
+                    .unwrap();
+            let signed = sign_entry(
+                &mut key_pair,
+                &public_key,
+                &log_id,
+                &seq_num,
+                skiplink,
+                backlink,
+                &operation,
+            )
+            .unwrap();
+
+            assert_eq!(signed.into_bytes(), expected.into_bytes());
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn signs_with_external_signer() {
+        use std::os::unix::net::UnixListener;
+        use std::thread;
+
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("signer.sock");
+        let listener = UnixListener::bind(&socket_path).unwrap();
+
+        let key_pair = KeyPair::new();
+        let public_key = key_pair.public_key();
+
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            serve_signer(&key_pair, stream.try_clone().unwrap(), stream).unwrap();
+            key_pair
+        });
+
+        let mut signer =
+            ExternalSigner::connect(&format!("unix:{}", socket_path.display())).unwrap();
+        assert_eq!(signer.public_key().unwrap(), public_key);
+
+        let log_id = LogId::new(1);
+        let seq_num = SeqNum::new(1).unwrap();
+        let signed = sign_entry(
+            &mut signer,
+            &public_key,
+            &log_id,
+            &seq_num,
+            None,
+            None,
+            &operation(),
+        )
+        .unwrap();
+
+        // Closing the connection stops the signer
+        drop(signer);
+        let mut key_pair = server.join().unwrap();
+
+        let expected = sign_entry(
+            &mut key_pair,
+            &public_key,
+            &log_id,
+            &seq_num,
+            None,
+            None,
+            &operation(),
+        )
+        .unwrap();
+        assert_eq!(signed.into_bytes(), expected.into_bytes());
+    }
+}
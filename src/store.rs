@@ -0,0 +1,206 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use anyhow::{bail, Context as ErrorContext, Result};
+use p2panda_rs::api::publish;
+use p2panda_rs::document::traits::AsDocument;
+use p2panda_rs::entry::decode::decode_entry;
+use p2panda_rs::entry::traits::{AsEncodedEntry, AsEntry};
+use p2panda_rs::operation::decode::decode_operation;
+use p2panda_rs::operation::traits::Schematic;
+use p2panda_rs::schema::system::{SchemaFieldView, SchemaView};
+use p2panda_rs::schema::{Schema as PandaSchema, SchemaId, SchemaName};
+use p2panda_rs::storage_provider::traits::DocumentStore;
+use p2panda_rs::test_utils::memory_store::MemoryStore;
+
+use crate::files::{Commit, LockFile, LockHeader, LOCK_FILE_VERSION};
+
+/// Schemas materialized in the store, together with the views they were built from.
+pub type BuiltSchemas = HashMap<SchemaName, (PandaSchema, SchemaView, Vec<SchemaFieldView>)>;
+
+/// Ids of schemas which are defined outside of the schema file, by their name.
+pub type ExternalSchemas = HashMap<SchemaName, SchemaId>;
+
+/// Publishes the given commits of a lock file to the store.
+pub async fn import_commits(store: &MemoryStore, commits: &[Commit]) -> Result<()> {
+    for commit in commits {
+        let plain_operation = decode_operation(&commit.operation)?;
+
+        let schema = match plain_operation.schema_id() {
+            SchemaId::SchemaDefinition(version) => PandaSchema::get_system(
+                SchemaId::SchemaDefinition(*version),
+            )
+            .with_context(|| {
+                format!(
+                    "Incompatible system schema definition version {version} used in commit {}",
+                    commit.entry_hash
+                )
+            })?,
+            SchemaId::SchemaFieldDefinition(version) => PandaSchema::get_system(
+                SchemaId::SchemaFieldDefinition(*version),
+            )
+            .with_context(|| {
+                format!(
+                    "Incompatible system schema field definition version {version} used in \
+                    commit {}",
+                    commit.entry_hash
+                )
+            })?,
+            value => bail!(
+                "Invalid schema id '{value}' detected in commit {}",
+                commit.entry_hash
+            ),
+        };
+
+        publish(
+            store,
+            schema,
+            &commit.entry,
+            &plain_operation,
+            &commit.operation,
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Builds all schemas which are defined by the schema definition documents in the store.
+pub async fn get_schemas(store: &MemoryStore) -> Result<BuiltSchemas> {
+    let mut built_schemas: BuiltSchemas = HashMap::new();
+
+    let definition_documents = store
+        .get_documents_by_schema(&SchemaId::SchemaDefinition(1))
+        .await?;
+
+    for definition_document in definition_documents {
+        // Deleted schema definitions don't have a view anymore
+        let document_view = match definition_document.view() {
+            Some(document_view) => document_view,
+            None => continue,
+        };
+
+        let schema_view = SchemaView::try_from(document_view)
+            .with_context(|| format!("Invalid schema definition {}", definition_document.id()))?;
+        let mut schema_field_views: Vec<SchemaFieldView> = Vec::new();
+
+        for field_view_id in schema_view.fields().iter() {
+            let field_view = store
+                .get_document_by_view_id(field_view_id)
+                .await?
+                .and_then(|field_document| field_document.view())
+                .with_context(|| {
+                    format!(
+                        "Field {field_view_id} of schema {} not found",
+                        schema_view.name()
+                    )
+                })?;
+
+            let schema_field_view = SchemaFieldView::try_from(field_view)
+                .with_context(|| format!("Invalid schema field definition {field_view_id}"))?;
+
+            schema_field_views.push(schema_field_view);
+        }
+
+        let schema = PandaSchema::from_views(schema_view.clone(), schema_field_views.clone())
+            .with_context(|| format!("Invalid schema {}", schema_view.name()))?;
+
+        if built_schemas
+            .insert(
+                schema.id().name(),
+                (schema, schema_view, schema_field_views),
+            )
+            .is_some()
+        {
+            bail!("Duplicate schema name detected in schema.lock");
+        }
+    }
+
+    Ok(built_schemas)
+}
+
+/// Returns the ids of all schemas defined by the commits of a lock file.
+pub async fn lock_schema_ids(commits: &[Commit]) -> Result<ExternalSchemas> {
+    let store = MemoryStore::default();
+    import_commits(&store, commits).await?;
+
+    Ok(get_schemas(&store)
+        .await?
+        .into_iter()
+        .map(|(schema_name, (schema, _, _))| (schema_name, schema.id().clone()))
+        .collect())
+}
+
+/// Builds a lock file with the header and schema index of the given commits.
+///
+/// All commits are replayed, which fails if any of them is invalid.
+pub async fn build_lock_file(commits: Vec<Commit>) -> Result<LockFile> {
+    let mut authors: Vec<String> = Vec::new();
+    let mut system_schemas = BTreeMap::new();
+
+    for commit in &commits {
+        if commit.entry.hash() != commit.entry_hash {
+            bail!("Commit {} has an invalid entry hash", commit.entry_hash);
+        }
+
+        let public_key = decode_entry(&commit.entry)?.public_key().to_string();
+        if !authors.contains(&public_key) {
+            authors.push(public_key);
+        }
+
+        match decode_operation(&commit.operation)?.schema_id() {
+            SchemaId::SchemaDefinition(version) => {
+                system_schemas.insert("schema_definition".to_string(), *version)
+            }
+            SchemaId::SchemaFieldDefinition(version) => {
+                system_schemas.insert("schema_field_definition".to_string(), *version)
+            }
+            value => bail!(
+                "Unexpected schema id '{value}' in commit {}",
+                commit.entry_hash
+            ),
+        };
+    }
+
+    let header = LockHeader {
+        authors,
+        fishy_version: env!("CARGO_PKG_VERSION").to_string(),
+        system_schemas,
+    };
+    let schemas = lock_schema_ids(&commits).await?.into_iter().collect();
+
+    Ok(LockFile::new(header, schemas, commits))
+}
+
+/// Makes sure the header and schema index of a lock file match its commits, by replaying them.
+pub async fn verify_lock_file(path: &Path, lock_file: &LockFile) -> Result<()> {
+    let built = build_lock_file(lock_file.commits.clone())
+        .await
+        .with_context(|| format!("Invalid commits in {} file", path.display()))?;
+
+    // Lock files of version 1 don't have a header and index yet
+    if lock_file.version == LOCK_FILE_VERSION
+        && (built.schemas != lock_file.schemas
+            || built.header.authors != lock_file.header.authors
+            || built.header.system_schemas != lock_file.header.system_schemas)
+    {
+        bail!(
+            "The header or schema index of {} doesn't match its commits",
+            path.display()
+        );
+    }
+
+    Ok(())
+}
+
+/// Returns the ids of all schemas of a lock file.
+///
+/// The schema index is used as it is, only lock files of version 1 which don't have one yet are
+/// replayed.
+pub async fn lock_file_schemas(lock_file: &LockFile) -> Result<ExternalSchemas> {
+    if lock_file.version == LOCK_FILE_VERSION {
+        return Ok(lock_file.schemas.clone().into_iter().collect());
+    }
+
+    lock_schema_ids(&lock_file.commits).await
+}
@@ -0,0 +1,205 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context as ErrorContext, Result};
+use p2panda_rs::graph::Graph;
+use p2panda_rs::schema::SchemaName;
+use p2panda_rs::test_utils::memory_store::MemoryStore;
+
+use crate::context::Context;
+use crate::files::{
+    read_schema_file, ConfigFile, RelationId, RelationSource, SchemaField, SchemaFile, SchemaFormat,
+};
+
+/// Folder of a workspace with its own schema, lock and key file.
+pub struct Member {
+    pub path: PathBuf,
+    pub schema_path: PathBuf,
+    pub schema_format: SchemaFormat,
+    pub lock_path: PathBuf,
+}
+
+impl Member {
+    /// Context with an empty store for the files of this member, sharing the config of the
+    /// workspace.
+    pub fn context(&self, workspace: &Context) -> Context {
+        Context::new(
+            MemoryStore::default(),
+            &self.schema_path,
+            &self.lock_path,
+            &workspace.config_path,
+            self.schema_format,
+        )
+    }
+
+    /// Reads the schema file of this member with all files it includes, see `read_schema_file`.
+    pub fn read_schema_file(&self) -> Result<SchemaFile> {
+        read_schema_file(&self.schema_path, self.schema_format)
+    }
+}
+
+fn defines(schema_file: &SchemaFile, schema_name: &SchemaName) -> bool {
+    schema_file.iter().any(|(name, _)| name == schema_name)
+}
+
+/// Reads the config file, if there is one.
+pub fn read_config_file(path: &Path) -> Result<Option<ConfigFile>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let config_file_str = fs::read_to_string(path)?;
+    let config_file: ConfigFile = toml::from_str(&config_file_str)
+        .with_context(|| format!("Invalid {} format", path.display()))?;
+
+    Ok(Some(config_file))
+}
+
+/// Returns the schema file of a member, which might use another format than the one of the
+/// workspace.
+fn member_schema_path(context: &Context, path: &Path) -> PathBuf {
+    let schema_path = path.join(&context.schema_path);
+    if schema_path.exists() {
+        return schema_path;
+    }
+
+    SchemaFormat::EXTENSIONS
+        .iter()
+        .map(|extension| schema_path.with_extension(extension))
+        .find(|path| path.exists())
+        .unwrap_or(schema_path)
+}
+
+fn read_member(context: &Context, path: PathBuf) -> Member {
+    let schema_path = member_schema_path(context, &path);
+    let schema_format = if schema_path.ends_with(&context.schema_path) {
+        context.schema_format
+    } else {
+        SchemaFormat::from_path(&schema_path)
+    };
+    let lock_path = path.join(&context.lock_path);
+
+    Member {
+        path,
+        schema_path,
+        schema_format,
+        lock_path,
+    }
+}
+
+/// Returns the indices of the members the given member has relations to.
+///
+/// Relations point to another member either with their `path` or by the name of a schema which
+/// is only defined in that member.
+fn member_dependencies(
+    members: &[Member],
+    schema_files: &[SchemaFile],
+    index: usize,
+) -> Result<Vec<usize>> {
+    let member = &members[index];
+    let mut dependencies = Vec::new();
+
+    for (schema_name, schema_item) in schema_files[index].iter() {
+        for (field_name, field) in schema_item.fields.iter() {
+            let schema = match field {
+                SchemaField::Relation { schema, .. } => schema,
+                _ => continue,
+            };
+
+            let related_name = match &schema.id {
+                RelationId::Name(name) => name,
+                RelationId::Id(_) => continue,
+            };
+
+            match &schema.external {
+                Some(RelationSource::Path(path)) => {
+                    let related_path = fs::canonicalize(member.path.join(path)).ok();
+                    let related = members.iter().position(|other| {
+                        related_path.is_some() && fs::canonicalize(&other.path).ok() == related_path
+                    });
+
+                    dependencies.extend(related);
+                }
+                Some(RelationSource::Git(_)) => (),
+                None if defines(&schema_files[index], related_name) => (),
+                None => {
+                    let related: Vec<usize> = (0..members.len())
+                        .filter(|other| defines(&schema_files[*other], related_name))
+                        .collect();
+
+                    if related.len() > 1 {
+                        bail!(
+                            "Field '{field_name}' of schema {schema_name} in {} relates to \
+                            schema {related_name}, which is defined by several members",
+                            member.path.display()
+                        );
+                    }
+
+                    dependencies.extend(related);
+                }
+            }
+        }
+    }
+
+    Ok(dependencies)
+}
+
+/// Reads the members of the workspace defined in the config file, in the order they are listed.
+///
+/// Only the paths of their files are looked up, the schema files are read when needed.
+///
+/// Returns `None` if the config file doesn't define a workspace.
+pub fn read_workspace(context: &Context) -> Result<Option<Vec<Member>>> {
+    let workspace = match read_config_file(&context.config_path)? {
+        Some(ConfigFile {
+            workspace: Some(workspace),
+            ..
+        }) => workspace,
+        _ => return Ok(None),
+    };
+
+    let base = context.config_path.parent().unwrap_or(Path::new(""));
+
+    Ok(Some(
+        workspace
+            .members
+            .into_iter()
+            .map(|path| read_member(context, base.join(path)))
+            .collect(),
+    ))
+}
+
+/// Orders the members so that every member comes after the members it has relations to.
+///
+/// Fails if the schema file of any member can't be read.
+pub fn sort_members(members: &[Member]) -> Result<Vec<&Member>> {
+    let schema_files = members
+        .iter()
+        .map(|member| {
+            member
+                .read_schema_file()
+                .with_context(|| format!("Could not read member {}", member.path.display()))
+        })
+        .collect::<Result<Vec<SchemaFile>>>()?;
+
+    let mut graph = Graph::new();
+
+    for index in 0..members.len() {
+        graph.add_node(&index, index);
+    }
+
+    for index in 0..members.len() {
+        for dependency in member_dependencies(members, &schema_files, index)? {
+            if dependency != index {
+                graph.add_link(&dependency, &index);
+            }
+        }
+    }
+
+    let sorted = graph
+        .sort()
+        .with_context(|| "Workspace members relate to each other in a cycle")?
+        .sorted();
+
+    Ok(sorted.into_iter().map(|index| &members[index]).collect())
+}